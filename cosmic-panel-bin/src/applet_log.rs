@@ -0,0 +1,280 @@
+//! Logging of the output of applets
+//! lines are sent to journald with fields naming the applet and its instance, its panel and output, and are optionally written to rotated log files.
+//! The journal protocol is spoken directly, because fields of the tracing spans don't reach journald.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    os::unix::net::UnixDatagram,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use cosmic_panel_config::{desktop_id, AppletLogs, LogFiles};
+use tracing::{error, info, warn};
+
+/// socket of the native journald protocol
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+/// length in bytes after which a line is truncated
+const MAX_LINE: usize = 16 * 1024;
+
+/// stream of an applet process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    /// syslog priority of the lines of the stream
+    fn priority(self) -> u8 {
+        match self {
+            Stream::Stdout => 6,
+            Stream::Stderr => 3,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+        }
+    }
+}
+
+/// Log of the output of an applet, shared by its streams and kept across restarts
+#[derive(Debug)]
+pub struct AppletLog {
+    instance_id: String,
+    panel_name: String,
+    /// name of the output of the panel, which changes when the panel follows the active output
+    output: Arc<Mutex<String>>,
+    config: Arc<Mutex<AppletLogs>>,
+    journal: Option<UnixDatagram>,
+    file: Option<LogFile>,
+    /// whether opening the log file failed, so it isn't retried for every line
+    file_failed: bool,
+    /// start of the current rate limit interval
+    interval_start: Instant,
+    /// lines logged within the interval
+    lines: u32,
+    /// lines dropped within the interval
+    suppressed: u32,
+}
+
+impl AppletLog {
+    pub fn new(
+        instance_id: String,
+        panel_name: String,
+        output: Arc<Mutex<String>>,
+        config: Arc<Mutex<AppletLogs>>,
+    ) -> Self {
+        let journal = Path::new(JOURNAL_SOCKET)
+            .exists()
+            .then(UnixDatagram::unbound)
+            .and_then(|socket| {
+                socket
+                    .map_err(|err| error!("Failed to create a journald socket: {}", err))
+                    .ok()
+            });
+        Self {
+            instance_id,
+            panel_name,
+            output,
+            config,
+            journal,
+            file: None,
+            file_failed: false,
+            interval_start: Instant::now(),
+            lines: 0,
+            suppressed: 0,
+        }
+    }
+
+    /// log a line of the applet, unless it exceeds the rate limit
+    pub fn log(&mut self, stream: Stream, line: &str) {
+        let config = self.config.lock().unwrap().clone();
+
+        let now = Instant::now();
+        if now.duration_since(self.interval_start) >= config.interval() {
+            if self.suppressed > 0 {
+                let message = format!(
+                    "Suppressed {} lines of {} which exceeded the rate limit",
+                    self.suppressed, self.instance_id
+                );
+                self.write(4, "panel", &message, &config);
+            }
+            self.interval_start = now;
+            self.lines = 0;
+            self.suppressed = 0;
+        }
+        if config.burst > 0 && self.lines >= config.burst {
+            self.suppressed += 1;
+            return;
+        }
+        self.lines += 1;
+
+        self.write(stream.priority(), stream.name(), line, &config);
+    }
+
+    fn write(&mut self, priority: u8, stream: &str, line: &str, config: &AppletLogs) {
+        let line = truncate(line);
+        let output = self.output.lock().unwrap().clone();
+
+        if !self.send_to_journal(priority, line, &output) {
+            let (applet, panel) = (&self.instance_id, &self.panel_name);
+            match priority {
+                0..=3 => {
+                    error!(applet = %applet, panel = %panel, output = %output, "{}: {}", applet, line)
+                }
+                4 => {
+                    warn!(applet = %applet, panel = %panel, output = %output, "{}: {}", applet, line)
+                }
+                _ => {
+                    info!(applet = %applet, panel = %panel, output = %output, "{}: {}", applet, line)
+                }
+            }
+        }
+
+        let Some(files) = config.files.as_ref() else {
+            self.file = None;
+            self.file_failed = false;
+            return;
+        };
+        if self.file.is_none() && !self.file_failed {
+            match self.log_file_path(&output).and_then(LogFile::open) {
+                Ok(file) => self.file = Some(file),
+                Err(err) => {
+                    error!(
+                        "Failed to open the log file of {}: {}",
+                        self.instance_id, err
+                    );
+                    self.file_failed = true;
+                }
+            }
+        }
+        if let Some(file) = self.file.as_mut() {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let entry = format!(
+                "[{}.{:03}] {}: {}\n",
+                timestamp.as_secs(),
+                timestamp.subsec_millis(),
+                stream,
+                line
+            );
+            if let Err(err) = file.write(&entry, files) {
+                error!(
+                    "Failed to write the log file of {}: {}",
+                    self.instance_id, err
+                );
+                self.file = None;
+                self.file_failed = true;
+            }
+        }
+    }
+
+    /// send the line with the fields of the applet, returns false if journald isn't available
+    fn send_to_journal(&self, priority: u8, line: &str, output: &str) -> bool {
+        let Some(journal) = self.journal.as_ref() else {
+            return false;
+        };
+        let mut datagram = Vec::with_capacity(line.len() + 256);
+        for (key, value) in [
+            ("PRIORITY", priority.to_string().as_str()),
+            ("SYSLOG_IDENTIFIER", desktop_id(&self.instance_id)),
+            ("APPLET_ID", desktop_id(&self.instance_id)),
+            ("APPLET_INSTANCE", self.instance_id.as_str()),
+            ("PANEL_NAME", self.panel_name.as_str()),
+            ("OUTPUT", output),
+            ("MESSAGE", line),
+        ] {
+            put_field(&mut datagram, key, value);
+        }
+        journal.send_to(&datagram, JOURNAL_SOCKET).is_ok()
+    }
+
+    /// `$XDG_STATE_HOME/cosmic-panel/applets/<panel>/<instance id>-<output>.log`
+    fn log_file_path(&self, output: &str) -> anyhow::Result<PathBuf> {
+        let dir = xdg::BaseDirectories::with_prefix("cosmic-panel")?
+            .create_state_directory(Path::new("applets").join(file_name(&self.panel_name)))?;
+        let name = if output.is_empty() {
+            format!("{}.log", file_name(&self.instance_id))
+        } else {
+            format!("{}-{}.log", file_name(&self.instance_id), file_name(output))
+        };
+        Ok(dir.join(name))
+    }
+}
+
+/// Log file which is rotated when it reaches its maximum size
+#[derive(Debug)]
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl LogFile {
+    fn open(path: PathBuf) -> anyhow::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { path, file, size })
+    }
+
+    fn write(&mut self, entry: &str, files: &LogFiles) -> anyhow::Result<()> {
+        if self.size > 0 && self.size + entry.len() as u64 > files.max_size {
+            self.rotate(files.max_files)?;
+        }
+        self.file.write_all(entry.as_bytes())?;
+        self.size += entry.len() as u64;
+        Ok(())
+    }
+
+    /// move `<name>.log` to `<name>.log.1`, and each older file one number up, dropping the oldest one
+    fn rotate(&mut self, max_files: u32) -> anyhow::Result<()> {
+        let rotated = |i: u32| PathBuf::from(format!("{}.{}", self.path.display(), i));
+        if max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(rotated(max_files));
+            for i in (1..max_files).rev() {
+                let _ = fs::rename(rotated(i), rotated(i + 1));
+            }
+            fs::rename(&self.path, rotated(1))?;
+        }
+        *self = Self::open(self.path.clone())?;
+        Ok(())
+    }
+}
+
+/// append a field in the format of the native journal protocol
+fn put_field(datagram: &mut Vec<u8>, key: &str, value: &str) {
+    datagram.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        datagram.push(b'\n');
+        datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        datagram.push(b'=');
+    }
+    datagram.extend_from_slice(value.as_bytes());
+    datagram.push(b'\n');
+}
+
+fn truncate(line: &str) -> &str {
+    if line.len() <= MAX_LINE {
+        return line;
+    }
+    let mut end = MAX_LINE;
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+    &line[..end]
+}
+
+/// replace the characters which can't be used in a file name
+fn file_name(name: &str) -> String {
+    name.replace(['/', '\0'], "_")
+}
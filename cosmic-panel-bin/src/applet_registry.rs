@@ -0,0 +1,257 @@
+//! Index of the desktop entries of applets, by desktop file id
+//! The application directories are read once, and read again when a desktop file in them changes.
+
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use freedesktop_desktop_entry::{default_paths, DesktopEntry, Iter};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use sctk::reexports::calloop::channel::SyncSender;
+use tracing::{error, warn};
+
+use crate::{exec::command_line, fd_broker::FdRequest, PanelCalloopMsg};
+
+/// Section of a panel which an applet prefers, read from `X-CosmicAppletSection`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AppletSection {
+    Start,
+    Center,
+    End,
+}
+
+/// How an applet is started
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AppletLaunch {
+    /// the command line of `Exec`, with its field codes expanded
+    Exec(Vec<String>),
+    /// `DBusActivatable=true`, the applet is activated through `org.freedesktop.Application` on the session bus
+    DBus,
+}
+
+impl fmt::Display for AppletLaunch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppletLaunch::Exec(command) => write!(f, "{}", command.join(" ")),
+            AppletLaunch::DBus => write!(f, "D-Bus activation"),
+        }
+    }
+}
+
+/// The values of a desktop entry which are needed to start an applet, and its layout hints
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppletDesktopEntry {
+    pub path: PathBuf,
+    pub launch: AppletLaunch,
+    /// whether the applet needs the wayland display of the compositor
+    pub requests_wayland_display: bool,
+    /// `X-CosmicPanelFds`, the fds which the applet gets from brokers each time it is started
+    pub fds: Vec<FdRequest>,
+    /// `X-CosmicAppletMinLength`, the length in logical pixels the panel reserves at least for the applet
+    pub min_length: Option<u32>,
+    /// `X-CosmicAppletMaxLength`, the length in logical pixels the panel reserves at most for the applet
+    pub max_length: Option<u32>,
+    /// `X-CosmicAppletExpand`, whether the applet fills the free length of a bar
+    pub expand: bool,
+    /// `X-CosmicAppletSection`, the preferred section of the applet, which orders the applets of a dock
+    pub section: Option<AppletSection>,
+    /// `X-CosmicAppletVertical`, whether the applet supports panels anchored to the left or right
+    pub vertical: bool,
+}
+
+impl AppletDesktopEntry {
+    fn read(path: &Path) -> Option<Self> {
+        let bytes = fs::read_to_string(path).ok()?;
+        let entry = DesktopEntry::decode(path, &bytes).ok()?;
+        let section =
+            entry
+                .desktop_entry("X-CosmicAppletSection")
+                .and_then(|section| match section.trim() {
+                    "Start" => Some(AppletSection::Start),
+                    "Center" => Some(AppletSection::Center),
+                    "End" => Some(AppletSection::End),
+                    section => {
+                        warn!(
+                            "Invalid value {} of X-CosmicAppletSection in {}",
+                            section,
+                            path.display()
+                        );
+                        None
+                    }
+                });
+        // D-Bus activation is preferred, and Exec is only used by launchers which don't support it
+        let launch = if hint(&entry, "DBusActivatable").unwrap_or(false) {
+            AppletLaunch::DBus
+        } else {
+            match command_line(entry.exec()?, &entry) {
+                Ok(command) => AppletLaunch::Exec(command),
+                Err(err) => {
+                    warn!("Invalid Exec in {}: {}", path.display(), err);
+                    return None;
+                }
+            }
+        };
+        Some(Self {
+            path: path.to_path_buf(),
+            launch,
+            requests_wayland_display: entry.desktop_entry("X-HostWaylandDisplay").is_some(),
+            fds: FdRequest::read(&entry, path),
+            min_length: hint(&entry, "X-CosmicAppletMinLength"),
+            max_length: hint(&entry, "X-CosmicAppletMaxLength"),
+            expand: hint(&entry, "X-CosmicAppletExpand").unwrap_or(false),
+            section,
+            vertical: hint(&entry, "X-CosmicAppletVertical").unwrap_or(true),
+        })
+    }
+
+    /// whether the applet has a hint for the length reserved for it
+    pub fn has_length_hints(&self) -> bool {
+        self.min_length.is_some() || self.max_length.is_some() || self.expand
+    }
+}
+
+/// parse the value of a key of the desktop entry, invalid values are logged and ignored
+fn hint<T: FromStr>(entry: &DesktopEntry, key: &str) -> Option<T> {
+    let value = entry.desktop_entry(key)?;
+    let parsed = value.trim().parse().ok();
+    if parsed.is_none() {
+        warn!(
+            "Invalid value {} of {} in {}",
+            value,
+            key,
+            entry.path.display()
+        );
+    }
+    parsed
+}
+
+/// Desktop entries of the installed applets
+#[derive(Debug, Default)]
+pub struct AppletRegistry {
+    entries: HashMap<String, AppletDesktopEntry>,
+    // whether a change was sent, which wasn't refreshed yet
+    pending: Arc<AtomicBool>,
+    // watches the application directories while the registry exists
+    watcher: Option<RecommendedWatcher>,
+    // watched directories, which are the nearest existing parents of the missing application directories
+    watched: Vec<(PathBuf, RecursiveMode)>,
+}
+
+impl AppletRegistry {
+    /// index the desktop entries, and send `PanelCalloopMsg::AppletsChanged` when a desktop file changes
+    pub fn new(panel_tx: SyncSender<PanelCalloopMsg>) -> Self {
+        let mut registry = Self::default();
+        registry.refresh();
+
+        // the events of an install are coalesced until the registry is refreshed
+        let pending = registry.pending.clone();
+        let app_dirs = default_paths();
+        let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let Ok(event) = event else {
+                return;
+            };
+            let is_desktop_file = event
+                .paths
+                .iter()
+                .any(|p| p.extension().map_or(false, |e| e == "desktop"));
+            // creating a missing application directory, or one of its parents, moves the watches
+            let is_app_dir = event.kind.is_create()
+                && event
+                    .paths
+                    .iter()
+                    .any(|p| app_dirs.iter().any(|dir| dir.starts_with(p)));
+            if (is_desktop_file || is_app_dir) && !pending.swap(true, Ordering::SeqCst) {
+                let _ = panel_tx.send(PanelCalloopMsg::AppletsChanged);
+            }
+        });
+        match watcher {
+            Ok(watcher) => {
+                registry.watcher = Some(watcher);
+                registry.watch_dirs();
+            }
+            Err(err) => error!("Failed to watch for installed applets: {}", err),
+        }
+        registry
+    }
+
+    /// watch the application directories, and the nearest existing parent of each missing one
+    /// so the directory is watched once it is created
+    fn watch_dirs(&mut self) {
+        let Some(watcher) = self.watcher.as_mut() else {
+            return;
+        };
+        let mut dirs: Vec<(PathBuf, RecursiveMode)> = Vec::new();
+        for path in default_paths() {
+            let dir = if path.is_dir() {
+                (path, RecursiveMode::Recursive)
+            } else {
+                let Some(parent) = path.ancestors().skip(1).find(|p| p.is_dir()) else {
+                    continue;
+                };
+                (parent.to_path_buf(), RecursiveMode::NonRecursive)
+            };
+            match dirs.iter_mut().find(|(p, _)| *p == dir.0) {
+                Some(watched) if dir.1 == RecursiveMode::Recursive => watched.1 = dir.1,
+                Some(_) => {}
+                None => dirs.push(dir),
+            }
+        }
+
+        for (path, mode) in &self.watched {
+            if !dirs.contains(&(path.clone(), *mode)) {
+                let _ = watcher.unwatch(path);
+            }
+        }
+        for (path, mode) in &dirs {
+            if self.watched.contains(&(path.clone(), *mode)) {
+                continue;
+            }
+            if let Err(err) = watcher.watch(path, *mode) {
+                warn!("Failed to watch {} for applets: {}", path.display(), err);
+            }
+        }
+        self.watched = dirs;
+    }
+
+    /// read the desktop entries again
+    pub fn refresh(&mut self) {
+        self.pending.store(false, Ordering::SeqCst);
+        self.watch_dirs();
+        let mut entries = HashMap::new();
+        for path in Iter::new(default_paths()) {
+            let Some(id) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            // earlier directories take precedence, like the desktop entries of the user
+            if entries.contains_key(id) {
+                continue;
+            }
+            if let Some(entry) = AppletDesktopEntry::read(&path) {
+                entries.insert(id.to_string(), entry);
+            }
+        }
+        self.entries = entries;
+    }
+
+    /// get the desktop entry of the applet
+    pub fn get(&self, id: &str) -> Option<&AppletDesktopEntry> {
+        self.entries.get(id)
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.entries.contains_key(id)
+    }
+
+    /// whether the applet can be started in a panel with the orientation
+    /// applets without a desktop entry are assumed to support both orientations
+    pub fn supports(&self, id: &str, is_horizontal: bool) -> bool {
+        is_horizontal || self.entries.get(id).map_or(true, |e| e.vertical)
+    }
+}
@@ -18,6 +18,7 @@ enum ConfigUpdate {
     Entries(Vec<String>),
     EntryChanged(String),
     Opacity(f32, String),
+    Reload,
 }
 
 #[derive(Debug, Clone)]
@@ -127,109 +128,61 @@ pub fn watch_cosmic_theme(
     ])
 }
 
+/// Sends a request to read the whole panel config again
+#[derive(Debug, Clone)]
+pub struct ConfigReload(channel::SyncSender<ConfigUpdate>);
+
+impl ConfigReload {
+    /// read the entries and every panel config again, and apply them like changes to the config
+    pub fn reload(&self) {
+        if let Err(err) = self.0.send(ConfigUpdate::Reload) {
+            error!("Failed to request a config reload: {:?}", err);
+        }
+    }
+}
+
 pub fn watch_config(
     config: &CosmicPanelContainerConfig,
     handle: LoopHandle<GlobalState<SpaceContainer>>,
-) -> Result<HashMap<String, RecommendedWatcher>, Box<dyn std::error::Error>> {
+) -> Result<(HashMap<String, RecommendedWatcher>, ConfigReload), Box<dyn std::error::Error>> {
     let (entries_tx, entries_rx) = channel::sync_channel::<ConfigUpdate>(30);
 
     let entries_tx_clone = entries_tx.clone();
     handle.insert_source(entries_rx, move |event, _, state| {
         match event {
             channel::Event::Msg(ConfigUpdate::Entries(entries)) => {
-                let to_update = entries
-                    .iter()
-                    .filter(|c| !state.space.config.config_list.iter().any(|e| e.name == **c))
-                    .map(|c| c.clone())
-                    .collect::<Vec<String>>();
-                info!("Received entries: {:?}", to_update);
-                for entry in to_update {
-                    let cosmic_config = match CosmicPanelConfig::cosmic_config(&entry) {
-                        Ok(config) => config,
-                        Err(err) => {
-                            error!("Failed to load cosmic config: {:?}", err);
-                            return;
-                        }
-                    };
-
-                    let entry = match CosmicPanelConfig::get_entry(&cosmic_config) {
-                        Ok(entry) => entry,
-                        Err((err, entry)) => {
-                            for error in err {
-                                error!("Failed to get entry value: {:?}", error);
-                            }
-                            entry
-                        }
-                    };
-
-                    let entries_tx_clone = entries_tx_clone.clone();
-                    let name_clone = entry.name.clone();
-                    let helper = CosmicPanelConfig::cosmic_config(&name_clone)
-                        .expect("Failed to load cosmic config");
-                    let watcher = helper
-                        .watch(move |_helper, _keys| {
-                            entries_tx_clone
-                                .send(ConfigUpdate::EntryChanged(name_clone.clone()))
-                                .expect("Failed to send Config Update");
-                        })
-                        .expect("Failed to watch cosmic config");
-                    state.space.watchers.insert(entry.name.clone(), watcher);
-
-                    state.space.update_space(
-                        entry,
-                        &state.client_state.compositor_state,
-                        state.client_state.fractional_scaling_manager.as_ref(),
-                        state.client_state.viewporter_state.as_ref(),
-                        &mut state.client_state.layer_state,
-                        &state.client_state.queue_handle,
-                        None,
-                    );
-                }
-                info!("Removing entries: {:?}", entries);
-                let to_remove = state
+                update_entries(state, entries, &entries_tx_clone);
+            }
+            channel::Event::Msg(ConfigUpdate::EntryChanged(entry)) => {
+                update_entry(state, &entry);
+            }
+            channel::Event::Msg(ConfigUpdate::Opacity(o, name)) => {
+                state.space.set_opacity(o, name);
+            }
+            channel::Event::Msg(ConfigUpdate::Reload) => {
+                info!("Reloading the panel config");
+                let entries = match CosmicPanelContainerConfig::cosmic_config()
+                    .and_then(|config| config.get::<Vec<String>>("entries"))
+                {
+                    Ok(entries) => entries,
+                    Err(err) => {
+                        error!("Failed to get entries: {:?}", err);
+                        return;
+                    }
+                };
+                let existing = state
                     .space
                     .config
                     .config_list
                     .iter()
-                    .filter(|c| !entries.contains(&c.name))
+                    .filter(|c| entries.contains(&c.name))
                     .map(|c| c.name.clone())
                     .collect::<Vec<String>>();
-                for entry in to_remove {
-                    state.space.remove_space(entry);
+                update_entries(state, entries, &entries_tx_clone);
+                for entry in existing {
+                    update_entry(state, &entry);
                 }
             }
-            channel::Event::Msg(ConfigUpdate::EntryChanged(entry)) => {
-                let cosmic_config = match CosmicPanelConfig::cosmic_config(&entry) {
-                    Ok(config) => config,
-                    Err(err) => {
-                        error!("Failed to load cosmic config: {:?}", err);
-                        return;
-                    }
-                };
-
-                let entry = match CosmicPanelConfig::get_entry(&cosmic_config) {
-                    Ok(entry) => entry,
-                    Err((err, entry)) => {
-                        for error in err {
-                            error!("Failed to get entry value: {:?}", error);
-                        }
-                        entry
-                    }
-                };
-                info!("Updating entry: {:?}", entry);
-                state.space.update_space(
-                    entry,
-                    &state.client_state.compositor_state,
-                    state.client_state.fractional_scaling_manager.as_ref(),
-                    state.client_state.viewporter_state.as_ref(),
-                    &mut state.client_state.layer_state,
-                    &state.client_state.queue_handle,
-                    None,
-                );
-            }
-            channel::Event::Msg(ConfigUpdate::Opacity(o, name)) => {
-                state.space.set_opacity(o, name);
-            }
             channel::Event::Closed => {}
         };
     })?;
@@ -285,5 +238,104 @@ pub fn watch_config(
         watchers.insert(entry.name.clone(), watcher);
     }
 
-    Ok(watchers)
+    Ok((watchers, ConfigReload(entries_tx)))
+}
+
+/// add the panels of new entries and watch their config, and remove the panels of removed entries
+fn update_entries(
+    state: &mut GlobalState<SpaceContainer>,
+    entries: Vec<String>,
+    entries_tx: &channel::SyncSender<ConfigUpdate>,
+) {
+    let to_update = entries
+        .iter()
+        .filter(|c| !state.space.config.config_list.iter().any(|e| e.name == **c))
+        .map(|c| c.clone())
+        .collect::<Vec<String>>();
+    info!("Received entries: {:?}", to_update);
+    for entry in to_update {
+        let cosmic_config = match CosmicPanelConfig::cosmic_config(&entry) {
+            Ok(config) => config,
+            Err(err) => {
+                error!("Failed to load cosmic config: {:?}", err);
+                return;
+            }
+        };
+
+        let entry = match CosmicPanelConfig::get_entry(&cosmic_config) {
+            Ok(entry) => entry,
+            Err((err, entry)) => {
+                for error in err {
+                    error!("Failed to get entry value: {:?}", error);
+                }
+                entry
+            }
+        };
+
+        let entries_tx_clone = entries_tx.clone();
+        let name_clone = entry.name.clone();
+        let helper =
+            CosmicPanelConfig::cosmic_config(&name_clone).expect("Failed to load cosmic config");
+        let watcher = helper
+            .watch(move |_helper, _keys| {
+                entries_tx_clone
+                    .send(ConfigUpdate::EntryChanged(name_clone.clone()))
+                    .expect("Failed to send Config Update");
+            })
+            .expect("Failed to watch cosmic config");
+        state.space.watchers.insert(entry.name.clone(), watcher);
+
+        state.space.update_space(
+            entry,
+            &state.client_state.compositor_state,
+            state.client_state.fractional_scaling_manager.as_ref(),
+            state.client_state.viewporter_state.as_ref(),
+            &mut state.client_state.layer_state,
+            &state.client_state.queue_handle,
+            None,
+        );
+    }
+    info!("Removing entries: {:?}", entries);
+    let to_remove = state
+        .space
+        .config
+        .config_list
+        .iter()
+        .filter(|c| !entries.contains(&c.name))
+        .map(|c| c.name.clone())
+        .collect::<Vec<String>>();
+    for entry in to_remove {
+        state.space.remove_space(entry);
+    }
+}
+
+/// read the config of the entry again, and apply it to its panel
+fn update_entry(state: &mut GlobalState<SpaceContainer>, entry: &str) {
+    let cosmic_config = match CosmicPanelConfig::cosmic_config(entry) {
+        Ok(config) => config,
+        Err(err) => {
+            error!("Failed to load cosmic config: {:?}", err);
+            return;
+        }
+    };
+
+    let entry = match CosmicPanelConfig::get_entry(&cosmic_config) {
+        Ok(entry) => entry,
+        Err((err, entry)) => {
+            for error in err {
+                error!("Failed to get entry value: {:?}", error);
+            }
+            entry
+        }
+    };
+    info!("Updating entry: {:?}", entry);
+    state.space.update_space(
+        entry,
+        &state.client_state.compositor_state,
+        state.client_state.fractional_scaling_manager.as_ref(),
+        state.client_state.viewporter_state.as_ref(),
+        &mut state.client_state.layer_state,
+        &state.client_state.queue_handle,
+        None,
+    );
 }
@@ -0,0 +1,77 @@
+//! Activation of applets with `DBusActivatable=true` in their desktop entry
+//! The panel calls `Activate` of `org.freedesktop.Application` on the session bus, and hands the applet
+//! the file descriptors of its `WAYLAND_SOCKET` variables and of its fd brokers in the platform data, along with its environment and arguments.
+//! The key of a descriptor is the name of its variable in lowercase, with `-` instead of `_`.
+
+use std::{
+    collections::HashMap,
+    os::fd::{AsRawFd, OwnedFd, RawFd},
+};
+
+use anyhow::{Context, Result};
+use zbus::{
+    dbus_proxy,
+    zvariant::{Fd, Value},
+    Connection,
+};
+
+/// variables which name a wayland socket of the applet
+const SOCKET_VARS: [&str; 2] = ["WAYLAND_SOCKET", "X_PRIVILEGED_WAYLAND_SOCKET"];
+
+#[dbus_proxy(interface = "org.freedesktop.Application")]
+trait Application {
+    /// activate the application
+    fn activate(&self, platform_data: HashMap<String, Value<'_>>) -> zbus::Result<()>;
+}
+
+/// get the object path of the application with the desktop file id, as the desktop entry specification defines it
+/// brokers of fds are found on the object path derived from their bus name the same way
+pub(crate) fn object_path(desktop_id: &str) -> String {
+    format!("/{}", desktop_id.replace('.', "/").replace('-', "_"))
+}
+
+/// activate the applet with the desktop file id
+/// `fd_vars` are the variables of the fds from brokers, the descriptors are closed in the panel once the bus passed them on
+pub async fn activate(
+    conn: &Connection,
+    desktop_id: &str,
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    fds: Vec<OwnedFd>,
+    fd_vars: &[String],
+) -> Result<()> {
+    let mut platform_data = HashMap::new();
+    let mut environment = HashMap::new();
+    for (key, value) in env {
+        if !SOCKET_VARS.contains(&key.as_str()) && !fd_vars.contains(&key) {
+            environment.insert(key, value);
+            continue;
+        }
+        let fd = value
+            .parse::<RawFd>()
+            .ok()
+            .filter(|fd| fds.iter().any(|f| f.as_raw_fd() == *fd))
+            .with_context(|| format!("Invalid file descriptor {} of {}", value, key))?;
+        platform_data.insert(
+            key.to_lowercase().replace('_', "-"),
+            Value::from(Fd::from(fd)),
+        );
+    }
+    platform_data.insert(
+        "cosmic-panel-environment".to_string(),
+        Value::from(environment),
+    );
+    platform_data.insert("cosmic-panel-args".to_string(), Value::from(args));
+
+    let proxy = ApplicationProxy::builder(conn)
+        .destination(desktop_id)?
+        .path(object_path(desktop_id))?
+        .build()
+        .await?;
+    proxy
+        .activate(platform_data)
+        .await
+        .with_context(|| format!("Failed to activate {}", desktop_id))?;
+    drop(fds);
+    Ok(())
+}
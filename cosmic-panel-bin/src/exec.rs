@@ -0,0 +1,224 @@
+//! Command lines of the `Exec` key of desktop entries, following the desktop entry specification
+//! The value is unescaped, split into arguments with its quoting rules, and its field codes are expanded.
+//! Applets aren't opened with files or URLs, so the field codes for them are removed.
+
+use anyhow::{bail, Result};
+use freedesktop_desktop_entry::DesktopEntry;
+
+/// get the command line of the `Exec` key of the desktop entry
+pub fn command_line(exec: &str, entry: &DesktopEntry) -> Result<Vec<String>> {
+    let mut command = Vec::new();
+    for arg in split(&unescape(exec))? {
+        match arg.as_str() {
+            // field codes for files and URLs are removed along with their argument
+            "%f" | "%F" | "%u" | "%U" => {}
+            "%i" => {
+                if let Some(icon) = entry.icon() {
+                    command.push("--icon".to_string());
+                    command.push(icon.to_string());
+                }
+            }
+            arg => command.push(expand(arg, entry)?),
+        }
+    }
+    if command.is_empty() {
+        bail!("Exec doesn't name an executable");
+    }
+    Ok(command)
+}
+
+/// replace the escape sequences of string values
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => unescaped.push(' '),
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('r') => unescaped.push('\r'),
+            Some(c) => {
+                // other escapes are left for the quoting rules of Exec
+                unescaped.push('\\');
+                unescaped.push(c);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// split the command line into its arguments
+/// arguments may be quoted in double quotes, where `"`, `` ` ``, `$` and `\` are escaped with a backslash
+fn split(exec: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut arg: Option<String> = None;
+    let mut chars = exec.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\n' => args.extend(arg.take()),
+            '"' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '`' | '$' | '\\')) => arg.push(c),
+                            Some(c) => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                            None => bail!("Unterminated quote in Exec"),
+                        },
+                        Some(c) => arg.push(c),
+                        None => bail!("Unterminated quote in Exec"),
+                    }
+                }
+            }
+            '\\' => {
+                let arg = arg.get_or_insert_with(String::new);
+                arg.extend(chars.next());
+            }
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+    Ok(args)
+}
+
+/// expand the field codes within an argument
+fn expand(arg: &str, entry: &DesktopEntry) -> Result<String> {
+    let mut expanded = String::with_capacity(arg.len());
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => expanded.push('%'),
+            Some('c') => expanded.push_str(&entry.name(None).unwrap_or_default()),
+            Some('k') => expanded.push_str(&entry.path.to_string_lossy()),
+            // deprecated field codes, and field codes which are only valid as a whole argument
+            Some('f' | 'F' | 'u' | 'U' | 'i' | 'd' | 'D' | 'n' | 'N' | 'v' | 'm') => {}
+            Some(c) => bail!("Invalid field code %{} in Exec", c),
+            None => bail!("Incomplete field code in Exec"),
+        }
+    }
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    const PATH: &str = "/usr/share/applications/com.system76.CosmicAppletTest.desktop";
+
+    fn command_line_of(exec: &str, icon: Option<&str>) -> Result<Vec<String>> {
+        let mut bytes = "[Desktop Entry]\nType=Application\nName=Test Applet\n".to_string();
+        if let Some(icon) = icon {
+            bytes.push_str(&format!("Icon={}\n", icon));
+        }
+        let entry = DesktopEntry::decode(Path::new(PATH), &bytes).unwrap();
+        command_line(exec, &entry)
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn unescape_string_escapes() {
+        assert_eq!(unescape(r"a\sb\tc\nd\re"), "a b\tc\nd\re");
+        // escapes of the quoting rules are left for `split`
+        assert_eq!(unescape(r#"\\ \" \$"#), r#"\\ \" \$"#);
+        assert_eq!(unescape(r"trailing\"), r"trailing\");
+    }
+
+    #[test]
+    fn split_unquoted() {
+        assert_eq!(split("applet").unwrap(), args(&["applet"]));
+        assert_eq!(
+            split(" applet  --flag\tvalue\n").unwrap(),
+            args(&["applet", "--flag", "value"])
+        );
+        assert_eq!(split(r"a\ b c").unwrap(), args(&["a b", "c"]));
+    }
+
+    #[test]
+    fn split_quoted() {
+        assert_eq!(
+            split(r#"applet "two words" x"y z""#).unwrap(),
+            args(&["applet", "two words", "xy z"])
+        );
+        assert_eq!(split(r#"applet """#).unwrap(), args(&["applet", ""]));
+        assert_eq!(
+            split(r#""a\"b" "a\`b" "a\$b" "a\\b""#).unwrap(),
+            args(&["a\"b", "a`b", "a$b", r"a\b"])
+        );
+        // other backslashes in quotes are kept
+        assert_eq!(split(r#""a\nb""#).unwrap(), args(&[r"a\nb"]));
+    }
+
+    #[test]
+    fn split_rejects_unterminated_quotes() {
+        assert!(split(r#"applet "open"#).is_err());
+        assert!(split(r#"applet "open\"#).is_err());
+    }
+
+    #[test]
+    fn empty_exec_is_rejected() {
+        assert!(command_line_of("", None).is_err());
+        assert!(command_line_of("  ", None).is_err());
+        assert!(command_line_of("%U", None).is_err());
+    }
+
+    #[test]
+    fn percent_is_escaped() {
+        assert_eq!(
+            command_line_of("applet 100%% %%c", None).unwrap(),
+            args(&["applet", "100%", "%c"])
+        );
+    }
+
+    #[test]
+    fn file_and_url_codes_are_removed() {
+        assert_eq!(
+            command_line_of("applet %f --flag %F %u %U", None).unwrap(),
+            args(&["applet", "--flag"])
+        );
+        // within an argument, they are removed from it
+        assert_eq!(
+            command_line_of("applet --file=%f", None).unwrap(),
+            args(&["applet", "--file="])
+        );
+    }
+
+    #[test]
+    fn icon_name_and_path_are_expanded() {
+        assert_eq!(
+            command_line_of("applet %i", Some("applet-icon")).unwrap(),
+            args(&["applet", "--icon", "applet-icon"])
+        );
+        assert_eq!(
+            command_line_of("applet %i", None).unwrap(),
+            args(&["applet"])
+        );
+        assert_eq!(
+            command_line_of("applet --name=%c %k", None).unwrap(),
+            args(&["applet", "--name=Test Applet", PATH])
+        );
+    }
+
+    #[test]
+    fn invalid_field_codes_are_rejected() {
+        assert!(command_line_of("applet %z", None).is_err());
+        assert!(command_line_of("applet --flag=%", None).is_err());
+    }
+}
@@ -0,0 +1,145 @@
+//! File descriptors which applets get from brokers on D-Bus
+//! A desktop entry lists the fds of its applet in `X-CosmicPanelFds`, as `<bus name>:<variable>` separated by `;`.
+//! Each time the applet is started, the panel calls `GetFd` of the broker with the bus name, on the interface with the same name
+//! and the object path derived from it, and passes the fd to the applet in the variable.
+//! The notifications daemon passes the panel its own socket, so its broker is reached through it instead of the session bus.
+
+use std::{
+    os::fd::{FromRawFd, IntoRawFd, OwnedFd},
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Result};
+use freedesktop_desktop_entry::DesktopEntry;
+use tracing::{error, warn};
+use zbus::{zvariant, Connection, Proxy};
+
+use crate::notifications::{notifications_conn, NotificationsSocketProxy};
+
+/// broker of the sockets of the notifications daemon, which `X-NotificationsApplet` requests
+pub const NOTIFICATIONS_BROKER: &str = "com.system76.NotificationsSocket";
+/// variable of the socket of the notifications daemon
+const NOTIFICATIONS_VAR: &str = "COSMIC_NOTIFICATIONS";
+/// time a broker has to answer
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// An fd which an applet needs, and the variable it is passed in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FdRequest {
+    /// bus name of the broker
+    pub broker: String,
+    pub var: String,
+}
+
+impl FdRequest {
+    /// read the fds requested by `X-CosmicPanelFds` and `X-NotificationsApplet`, invalid requests are logged and ignored
+    pub fn read(entry: &DesktopEntry, path: &Path) -> Vec<Self> {
+        let mut requests: Vec<Self> = entry
+            .desktop_entry("X-CosmicPanelFds")
+            .unwrap_or_default()
+            .split(';')
+            .map(str::trim)
+            .filter(|request| !request.is_empty())
+            .filter_map(|request| {
+                let parsed = request
+                    .split_once(':')
+                    .map(|(broker, var)| (broker.trim(), var.trim()))
+                    .filter(|(broker, var)| !broker.is_empty() && !var.is_empty());
+                if parsed.is_none() {
+                    warn!(
+                        "Invalid fd request {} of X-CosmicPanelFds in {}",
+                        request,
+                        path.display()
+                    );
+                }
+                parsed.map(|(broker, var)| Self {
+                    broker: broker.to_string(),
+                    var: var.to_string(),
+                })
+            })
+            .collect();
+        if entry.desktop_entry("X-NotificationsApplet").is_some()
+            && !requests.iter().any(|r| r.var == NOTIFICATIONS_VAR)
+        {
+            requests.push(Self {
+                broker: NOTIFICATIONS_BROKER.to_string(),
+                var: NOTIFICATIONS_VAR.to_string(),
+            });
+        }
+        requests
+    }
+}
+
+/// Connections to the brokers, which are made when they are first needed
+#[derive(Debug, Default)]
+pub struct FdBrokers {
+    notifications: Option<NotificationsSocketProxy<'static>>,
+    session: Option<Connection>,
+}
+
+impl FdBrokers {
+    /// connect to the notifications daemon through the socket it passed the panel
+    pub async fn new() -> Self {
+        let mut brokers = Self::default();
+        brokers.connect_notifications().await;
+        brokers
+    }
+
+    async fn connect_notifications(&mut self) {
+        self.notifications = match tokio::time::timeout(TIMEOUT, notifications_conn()).await {
+            Ok(Ok(p)) => Some(p),
+            err => {
+                error!("Failed to connect to the notifications daemon {:?}", err);
+                None
+            }
+        };
+    }
+
+    /// get the connection to the notifications daemon
+    pub fn notifications(&self) -> Option<&NotificationsSocketProxy<'static>> {
+        self.notifications.as_ref()
+    }
+
+    /// get the connection to the session bus
+    pub async fn session(&mut self) -> Result<&Connection> {
+        if self.session.is_none() {
+            let conn = Connection::session()
+                .await
+                .context("Failed to connect to the session bus")?;
+            self.session = Some(conn);
+        }
+        Ok(self.session.as_ref().unwrap())
+    }
+
+    /// get an fd from each broker, with the variable it is passed in
+    pub async fn get_fds(&mut self, requests: &[FdRequest]) -> Result<Vec<(String, OwnedFd)>> {
+        let mut fds = Vec::with_capacity(requests.len());
+        for request in requests {
+            let fd = self
+                .get_fd(&request.broker)
+                .await
+                .with_context(|| format!("Failed to get an fd from {}", request.broker))?;
+            fds.push((request.var.clone(), fd));
+        }
+        Ok(fds)
+    }
+
+    async fn get_fd(&mut self, broker: &str) -> Result<OwnedFd> {
+        let fd: zvariant::OwnedFd = if broker == NOTIFICATIONS_BROKER {
+            if self.notifications.is_none() {
+                self.connect_notifications().await;
+            }
+            let proxy = self
+                .notifications
+                .as_ref()
+                .ok_or_else(|| anyhow!("No connection to the notifications daemon"))?;
+            tokio::time::timeout(TIMEOUT, proxy.get_fd()).await??
+        } else {
+            let path = crate::dbus_activation::object_path(broker);
+            let proxy = Proxy::new(self.session().await?, broker, path, broker).await?;
+            tokio::time::timeout(TIMEOUT, proxy.call("GetFd", &())).await??
+        };
+        Ok(unsafe { OwnedFd::from_raw_fd(fd.into_raw_fd()) })
+    }
+}
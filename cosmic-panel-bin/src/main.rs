@@ -1,22 +1,30 @@
+mod applet_log;
+mod applet_registry;
 mod config_watching;
+mod dbus_activation;
+mod exec;
+mod fd_broker;
 mod notifications;
+mod resource_limits;
 mod space;
 mod space_container;
 
 use anyhow::Result;
 use cctk::wayland_client::protocol::wl_output::WlOutput;
 use config_watching::{watch_config, watch_cosmic_theme};
-use cosmic_panel_config::CosmicPanelConfig;
+use cosmic_panel_config::{CosmicPanelConfig, CosmicPanelContainerConfig};
+use fd_broker::FdBrokers;
 use launch_pad::{ProcessKey, ProcessManager};
-use notifications::notifications_conn;
+use notifications::{notify, NotificationsSocketProxy};
 use sctk::reexports::calloop::channel::SyncSender;
-use smithay::reexports::{calloop, wayland_server::backend::ClientId};
-use std::{
-    collections::HashMap,
-    mem,
-    os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd},
-    time::Duration,
+use smithay::reexports::{
+    calloop::{
+        self,
+        signals::{Signal, Signals},
+    },
+    wayland_server::backend::ClientId,
 };
+use std::{collections::HashMap, mem, os::fd::AsRawFd, time::Duration};
 use tokio::{runtime, sync::mpsc};
 use tracing::{error, info, warn};
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
@@ -24,10 +32,20 @@ use xdg_shell_wrapper::{
     client_state::ClientState, run, server_state::ServerState, shared_state::GlobalState,
 };
 
+/// time the applets get to stop when the panel is terminated
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+/// time an applet has to reply to its activation before it is marked as failed
+const ACTIVATION_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Debug)]
 pub enum PanelCalloopMsg {
     ClientSocketPair(ClientId),
     RestartSpace(CosmicPanelConfig, WlOutput),
+    ActiveOutput(WlOutput),
+    AppletFailed(String, String),
+    /// the indicator of a failed applet was pressed, so its popup is opened
+    ShowFailures,
+    AppletsChanged,
 }
 
 fn main() -> Result<()> {
@@ -51,19 +69,32 @@ fn main() -> Result<()> {
     log_panics::init();
 
     let arg = std::env::args().nth(1);
-    let usage = "USAGE: cosmic-panel";
+    let usage = "USAGE: cosmic-panel [export [<file>] | import <file>]";
+    let mut config_error = None;
     let config = match arg.as_ref().map(|s| &s[..]) {
         Some(arg) if arg == "--help" || arg == "-h" => {
             println!("{}", usage);
             std::process::exit(1);
         }
-        None => match cosmic_panel_config::CosmicPanelContainerConfig::load() {
+        Some("export") => return export_config(std::env::args().nth(2)),
+        Some("import") => {
+            let Some(path) = std::env::args().nth(2) else {
+                println!("{}", usage);
+                std::process::exit(1);
+            };
+            return import_config(&path);
+        }
+        // started by the panel in place of an applet with resource limits
+        Some(resource_limits::SUBCOMMAND) => {
+            return resource_limits::exec_limited(std::env::args().skip(2))
+        }
+        None => match CosmicPanelContainerConfig::load() {
             Ok(c) => c,
             Err((errors, c)) => {
                 for e in errors {
                     error!("Panel Entry Error: {:?}", e);
                 }
-                let _ = c.write_entries();
+                config_error = Some(recover_broken_config(&c));
                 c
             }
         },
@@ -73,6 +104,10 @@ fn main() -> Result<()> {
         }
     };
 
+    // the signals are blocked before any thread is spawned, so every thread of the panel inherits the mask
+    // and they are only read from the signal source. Applets are spawned with an empty mask
+    let signals = Signals::new(&[Signal::SIGTERM, Signal::SIGINT, Signal::SIGHUP])?;
+
     let (applet_tx, mut applet_rx) = mpsc::channel(200);
     let (calloop_tx, calloop_rx): (SyncSender<PanelCalloopMsg>, _) =
         calloop::channel::sync_channel(100);
@@ -83,12 +118,16 @@ fn main() -> Result<()> {
     let event_loop = calloop::EventLoop::try_new()?;
 
     let handle = event_loop.handle();
-    match watch_config(&space.config, handle) {
-        Ok(watchers) => {
+    let config_reload = match watch_config(&space.config, handle) {
+        Ok((watchers, config_reload)) => {
             info!("Watching panel config successful");
             space.watchers = watchers;
+            Some(config_reload)
+        }
+        Err(e) => {
+            warn!("Failed to watch config: {:?}", e);
+            None
         }
-        Err(e) => warn!("Failed to watch config: {:?}", e),
     };
     match watch_cosmic_theme(event_loop.handle()) {
         Ok(w) => mem::forget(w),
@@ -116,6 +155,32 @@ fn main() -> Result<()> {
                                 Some(o),
                             );
                         }
+                        PanelCalloopMsg::AppletFailed(id, error) => {
+                            state.space.applet_failed(&id, error);
+                        }
+                        PanelCalloopMsg::ShowFailures => {
+                            state.space.show_failures(
+                                &state.client_state.compositor_state,
+                                &state.client_state.xdg_shell_state,
+                                &state.client_state.shm_state,
+                                &state.client_state.queue_handle,
+                            );
+                        }
+                        PanelCalloopMsg::AppletsChanged => {
+                            state
+                                .space
+                                .applets_changed(&state.client_state.queue_handle);
+                        }
+                        PanelCalloopMsg::ActiveOutput(o) => {
+                            state.space.move_active_spaces(
+                                o,
+                                &state.client_state.compositor_state,
+                                state.client_state.fractional_scaling_manager.as_ref(),
+                                state.client_state.viewporter_state.as_ref(),
+                                &mut state.client_state.layer_state,
+                                &state.client_state.queue_handle,
+                            );
+                        }
                     },
                     calloop::channel::Event::Closed => {}
                 };
@@ -123,6 +188,33 @@ fn main() -> Result<()> {
         )
         .expect("failed to insert dbus event source");
 
+    event_loop
+        .handle()
+        .insert_source(
+            signals,
+            move |event, _, state: &mut GlobalState<space_container::SpaceContainer>| {
+                let signal = event.signal();
+                match signal {
+                    Signal::SIGHUP => match config_reload.as_ref() {
+                        Some(config_reload) => config_reload.reload(),
+                        None => warn!("Can't reload the config, it isn't watched"),
+                    },
+                    _ => {
+                        info!("Received {:?}, stopping the applets", signal);
+                        if state.space.shutdown(SHUTDOWN_TIMEOUT) {
+                            std::process::exit(0);
+                        }
+                        error!(
+                            "The applets didn't stop within {}ms",
+                            SHUTDOWN_TIMEOUT.as_millis()
+                        );
+                        std::process::exit(1);
+                    }
+                }
+            },
+        )
+        .expect("failed to insert signal event source");
+
     std::thread::spawn(move || -> anyhow::Result<()> {
         let rt = runtime::Builder::new_current_thread()
             .enable_all()
@@ -132,108 +224,137 @@ fn main() -> Result<()> {
         rt.block_on(async move {
             let process_manager = ProcessManager::new().await;
             let _ = process_manager
-                .set_restart_mode(launch_pad::RestartMode::ExponentialBackoff(
-                    Duration::from_millis(2),
-                ))
+                .set_restart_mode(launch_pad::RestartMode::Instant)
                 .await;
+            // the restart policy of each applet delays its restarts, and stops it when it keeps exiting
             let _ = process_manager.set_max_restarts(999999).await;
 
-            let mut notifications_proxy =
-                match tokio::time::timeout(Duration::from_secs(1), notifications_conn()).await {
-                    Ok(Ok(p)) => Some(p),
-                    err => {
-                        error!("Failed to connect to the notifications daemon {:?}", err);
-                        None
-                    }
-                };
+            let mut brokers = FdBrokers::new().await;
+            if let (Some(proxy), Some(body)) = (brokers.notifications(), config_error) {
+                if let Err(err) = notify(proxy, "Failed to load the panel config", &body).await {
+                    error!("Failed to notify about the panel config {}", err);
+                }
+            }
 
             while let Some(msg) = applet_rx.recv().await {
                 match msg {
-                    space::AppletMsg::NewProcess(id, process) => {
-                        if let Ok(key) = process_manager.start(process).await {
-                            let entry = process_ids.entry(id).or_insert_with(|| Vec::new());
-                            entry.push(key);
-                        }
-                    }
-                    space::AppletMsg::NewNotificationsProcess(
+                    space::AppletMsg::NewProcess(
                         id,
                         mut process,
                         mut env,
                         mut fds,
+                        fd_requests,
                     ) => {
-                        let Some(proxy) = notifications_proxy.as_mut() else {
-                            notifications_proxy = match tokio::time::timeout(
-                                Duration::from_secs(1),
-                                notifications_conn(),
-                            )
-                            .await
-                            {
-                                Ok(Ok(p)) => Some(p),
-                                err => {
-                                    error!(
-                                        "Failed to connect to the notifications daemon {:?}",
-                                        err
-                                    );
-                                    None
+                        match brokers.get_fds(&fd_requests).await {
+                            Ok(broker_fds) => {
+                                for (var, fd) in broker_fds {
+                                    env.push((var, fd.as_raw_fd().to_string()));
+                                    fds.push(fd);
                                 }
-                            };
-                            warn!("Can't start notifications applet without a connection");
-                            continue;
-                        };
-                        info!("Getting fd for notifications applet");
-                        let notif_fd = match tokio::time::timeout(
-                            Duration::from_secs(1),
-                            proxy.get_fd(),
-                        )
-                        .await
-                        {
-                            Ok(Ok(fd)) => fd,
-                            Ok(Err(err)) => {
-                                error!("Failed to get fd for the notifications applet {}", err);
-                                continue;
                             }
                             Err(err) => {
-                                error!("Failed to get fd for the notifications applet {}", err);
+                                let reason = format!("{} couldn't be started: {:#}", id, err);
+                                error!("{}", reason);
+                                notify_failure(brokers.notifications(), &reason).await;
+                                let _ = calloop_tx.send(PanelCalloopMsg::AppletFailed(id, reason));
                                 continue;
                             }
-                        };
-                        let notif_fd = unsafe { OwnedFd::from_raw_fd(notif_fd.into_raw_fd()) };
-                        env.push((
-                            "COSMIC_NOTIFICATIONS".to_string(),
-                            notif_fd.as_raw_fd().to_string(),
-                        ));
-                        fds.push(notif_fd);
+                        }
                         process = process.with_fds(move || fds);
                         process = process.with_env(env);
-                        info!("Starting notifications applet");
                         if let Ok(key) = process_manager.start(process).await {
                             let entry = process_ids.entry(id).or_insert_with(|| Vec::new());
                             entry.push(key);
                         }
                     }
+                    space::AppletMsg::Activate(
+                        id,
+                        desktop_id,
+                        args,
+                        mut env,
+                        mut fds,
+                        fd_requests,
+                    ) => {
+                        let mut fd_vars = Vec::new();
+                        let conn = match brokers.get_fds(&fd_requests).await {
+                            Ok(broker_fds) => {
+                                for (var, fd) in broker_fds {
+                                    env.push((var.clone(), fd.as_raw_fd().to_string()));
+                                    fds.push(fd);
+                                    fd_vars.push(var);
+                                }
+                                brokers.session().await.cloned()
+                            }
+                            Err(err) => Err(err),
+                        };
+                        let notifications = brokers.notifications().cloned();
+                        let calloop_tx = calloop_tx.clone();
+                        // the applet is activated in its own task, so an applet which is slow to reply doesn't hold up the other messages
+                        tokio::spawn(async move {
+                            let res = match conn {
+                                Ok(conn) => tokio::time::timeout(
+                                    ACTIVATION_TIMEOUT,
+                                    dbus_activation::activate(
+                                        &conn,
+                                        &desktop_id,
+                                        args,
+                                        env,
+                                        fds,
+                                        &fd_vars,
+                                    ),
+                                )
+                                .await
+                                .unwrap_or_else(|_| {
+                                    Err(anyhow::anyhow!(
+                                        "No reply within {}s",
+                                        ACTIVATION_TIMEOUT.as_secs()
+                                    ))
+                                }),
+                                Err(err) => Err(err),
+                            };
+                            if let Err(err) = res {
+                                let reason =
+                                    format!("{} couldn't be activated: {:#}", desktop_id, err);
+                                error!("{}", reason);
+                                notify_failure(notifications.as_ref(), &reason).await;
+                                let _ = calloop_tx.send(PanelCalloopMsg::AppletFailed(id, reason));
+                            }
+                        });
+                    }
                     space::AppletMsg::ClientSocketPair(client_id) => {
                         let _ = calloop_tx.send(PanelCalloopMsg::ClientSocketPair(client_id));
                     }
                     space::AppletMsg::Cleanup(id) => {
-                        for id in process_ids.remove(&id).unwrap_or_default() {
-                            let _ = process_manager.stop_process(id).await;
+                        // the id of a panel stops the processes of all its applets
+                        let prefix = format!("{}/", id);
+                        let ids: Vec<_> = process_ids
+                            .keys()
+                            .filter(|key| **key == id || key.starts_with(&prefix))
+                            .cloned()
+                            .collect();
+                        for id in ids {
+                            for key in process_ids.remove(&id).unwrap_or_default() {
+                                let _ = process_manager.stop_process(key).await;
+                            }
                         }
                     }
-                    space::AppletMsg::NeedNewNotificationFd(sender) => {
-                        let Some(proxy) = notifications_proxy.as_mut() else {
-                            warn!("Can't start notifications applet without a connection");
-                            continue;
-                        };
-                        let fd = match proxy.get_fd().await {
-                            Ok(fd) => fd,
-                            Err(err) => {
-                                error!("Failed to get fd for the notifications applet {}", err);
-                                continue;
+                    space::AppletMsg::Shutdown(done) => {
+                        for (_, keys) in process_ids.drain() {
+                            for key in keys {
+                                let _ = process_manager.stop_process(key).await;
                             }
-                        };
-                        let fd = unsafe { OwnedFd::from_raw_fd(fd.into_raw_fd()) };
-
-                        _ = sender.send(fd);
+                        }
+                        let _ = done.send(());
+                    }
+                    space::AppletMsg::Failed(id, reason) => {
+                        for key in process_ids.remove(&id).unwrap_or_default() {
+                            let _ = process_manager.stop_process(key).await;
+                        }
+                        notify_failure(brokers.notifications(), &reason).await;
+                        let _ = calloop_tx.send(PanelCalloopMsg::AppletFailed(id, reason));
+                    }
+                    space::AppletMsg::NeedFds(requests, sender) => {
+                        _ = sender.send(brokers.get_fds(&requests).await);
                     }
                 };
             }
@@ -259,3 +380,94 @@ fn main() -> Result<()> {
     )?;
     Ok(())
 }
+
+/// show the reason an applet failed in a notification
+async fn notify_failure(proxy: Option<&NotificationsSocketProxy<'static>>, reason: &str) {
+    let Some(proxy) = proxy else {
+        return;
+    };
+    let body = format!("{}. It can be restarted by restarting the panel.", reason);
+    if let Err(err) = notify(proxy, "An applet of the panel failed", &body).await {
+        error!("Failed to notify about the failed applet {}", err);
+    }
+}
+
+/// write the panel config to the file, or to stdout
+fn export_config(path: Option<String>) -> Result<()> {
+    let config = match CosmicPanelContainerConfig::load() {
+        Ok(c) => c,
+        Err((errors, c)) => {
+            for e in errors {
+                warn!("Panel Entry Error: {:?}", e);
+            }
+            c
+        }
+    };
+    let document = config.export()?;
+    match path {
+        Some(path) => std::fs::write(path, document)?,
+        None => println!("{}", document),
+    }
+    Ok(())
+}
+
+/// add the profiles of the file to the panel config
+fn import_config(path: &str) -> Result<()> {
+    let document = std::fs::read_to_string(path)?;
+    let mut config = match CosmicPanelContainerConfig::load() {
+        Ok(c) => c,
+        Err((errors, _)) => {
+            for e in errors {
+                error!("Panel Entry Error: {:?}", e);
+            }
+            anyhow::bail!("The current panel config failed to load, not importing over it");
+        }
+    };
+    let validation = config.import(&document)?;
+    for issue in validation.errors {
+        error!("Skipped invalid profile {}", issue);
+    }
+    for issue in validation.warnings {
+        warn!("Clamped imported profile {}", issue);
+    }
+    Ok(())
+}
+
+/// back up the broken config before writing the fallback config over it
+/// returns a description of the failure for the user
+fn recover_broken_config(config: &CosmicPanelContainerConfig) -> String {
+    let keys = CosmicPanelContainerConfig::invalid_keys();
+    let keys = if keys.is_empty() {
+        "the panel config".to_string()
+    } else {
+        keys.join(", ")
+    };
+    match CosmicPanelContainerConfig::backup() {
+        Ok(backup) => {
+            if let Err(err) = config.write_entries() {
+                error!("Failed to write the fallback panel config {:?}", err);
+            }
+            match backup {
+                Some(path) => {
+                    info!("Backed up the broken panel config to {}", path.display());
+                    format!(
+                        "Failed to load {}. The previous config was backed up to {}.",
+                        keys,
+                        path.display()
+                    )
+                }
+                None => format!("Failed to load {}. Default values are used.", keys),
+            }
+        }
+        Err(err) => {
+            error!(
+                "Failed to back up the panel config, leaving it untouched {}",
+                err
+            );
+            format!(
+                "Failed to load {}. The config could not be backed up, so it was left untouched.",
+                keys
+            )
+        }
+    }
+}
@@ -4,9 +4,12 @@ use smithay::reexports::rustix::{
     self,
     io::{fcntl_getfd, fcntl_setfd, FdFlags},
 };
-use std::os::{
-    fd::{FromRawFd, OwnedFd, RawFd},
-    unix::net::UnixStream,
+use std::{
+    collections::HashMap,
+    os::{
+        fd::{FromRawFd, OwnedFd, RawFd},
+        unix::net::UnixStream,
+    },
 };
 use tracing::info;
 use zbus::{dbus_proxy, ConnectionBuilder};
@@ -20,6 +23,27 @@ trait NotificationsSocket {
     /// get an fd for an applet
     fn get_fd(&self) -> zbus::Result<zbus::zvariant::OwnedFd>;
 }
+
+#[dbus_proxy(
+    default_service = "org.freedesktop.Notifications",
+    interface = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+    /// show a notification
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, zbus::zvariant::Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+}
+
 pub async fn notifications_conn() -> Result<NotificationsSocketProxy<'static>> {
     info!("Connecting to notifications daemon");
     let fd_num = std::env::var(PANEL_NOTIFICATIONS_FD)?;
@@ -46,3 +70,25 @@ pub async fn notifications_conn() -> Result<NotificationsSocketProxy<'static>> {
 
     Ok(proxy)
 }
+
+/// show a notification to the user through the connection to the notifications daemon
+pub async fn notify(
+    socket: &NotificationsSocketProxy<'static>,
+    summary: &str,
+    body: &str,
+) -> Result<()> {
+    let proxy = NotificationsProxy::new(socket.inner().connection()).await?;
+    proxy
+        .notify(
+            "COSMIC Panel",
+            0,
+            "dialog-warning",
+            summary,
+            body,
+            &[],
+            HashMap::new(),
+            -1,
+        )
+        .await?;
+    Ok(())
+}
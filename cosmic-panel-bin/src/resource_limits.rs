@@ -0,0 +1,91 @@
+//! Resource limits of applet processes
+//! `launch_pad` has no hook which runs in the child before it executes the applet,
+//! so applets with limits are started through the panel executable, which applies the limits and then executes the applet.
+
+use std::{io, os::unix::process::CommandExt, process::Command};
+
+use anyhow::{Context, Result};
+use cosmic_panel_config::ResourceLimits;
+use tracing::{error, warn};
+
+/// subcommand of the panel which applies the limits to itself before executing the applet
+pub const SUBCOMMAND: &str = "limit-applet";
+
+/// get the executable and arguments which start the applet with the limits
+pub fn limited_command(
+    exec: String,
+    args: Vec<String>,
+    limits: &ResourceLimits,
+) -> (String, Vec<String>) {
+    if limits.is_empty() {
+        return (exec, args);
+    }
+    let panel = match std::env::current_exe() {
+        Ok(panel) => panel,
+        Err(err) => {
+            error!(
+                "Failed to find the panel executable, starting {} without resource limits: {}",
+                exec, err
+            );
+            return (exec, args);
+        }
+    };
+    let limits = match ron::to_string(limits) {
+        Ok(limits) => limits,
+        Err(err) => {
+            error!(
+                "Failed to serialize the resource limits, starting {} without them: {}",
+                exec, err
+            );
+            return (exec, args);
+        }
+    };
+    let mut limited_args = vec![SUBCOMMAND.to_string(), limits, exec];
+    limited_args.extend(args);
+    (panel.to_string_lossy().into_owned(), limited_args)
+}
+
+/// apply the limits in the first argument to this process, and replace it with the applet in the remaining arguments
+/// only returns if the applet can't be executed
+pub fn exec_limited(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let limits = args.next().context("Missing resource limits")?;
+    let limits: ResourceLimits = ron::from_str(&limits).context("Invalid resource limits")?;
+    let exec = args.next().context("Missing applet executable")?;
+
+    // the applet is started anyway, so a limit which can't be applied is only logged
+    for (name, resource, limit) in [
+        ("address space", libc::RLIMIT_AS, limits.address_space),
+        ("open files", libc::RLIMIT_NOFILE, limits.open_files),
+        ("CPU time", libc::RLIMIT_CPU, limits.cpu_time),
+    ] {
+        let Some(limit) = limit else {
+            continue;
+        };
+        let rlimit = libc::rlimit {
+            rlim_cur: limit as libc::rlim_t,
+            rlim_max: limit as libc::rlim_t,
+        };
+        if unsafe { libc::setrlimit(resource, &rlimit) } != 0 {
+            warn!(
+                "Failed to limit the {} of {} to {}: {}",
+                name,
+                exec,
+                limit,
+                io::Error::last_os_error()
+            );
+        }
+    }
+    if let Some(nice) = limits.nice {
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } != 0 {
+            warn!(
+                "Failed to set the nice level of {} to {}: {}",
+                exec,
+                nice,
+                io::Error::last_os_error()
+            );
+        }
+    }
+
+    let err = Command::new(&exec).args(args).exec();
+    Err(err).with_context(|| format!("Failed to execute {}", exec))
+}
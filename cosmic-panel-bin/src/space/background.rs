@@ -0,0 +1,125 @@
+use cosmic_panel_config::{parse_color, CosmicPanelBackground, GradientStop, ImageScaling};
+use image::{imageops, Rgba, RgbaImage};
+use smithay::utils::{Physical, Size};
+use tracing::error;
+
+use super::PanelSpace;
+
+impl PanelSpace {
+    /// render a gradient or image background with the size of the panel
+    /// returns None for single color backgrounds, which are drawn with `bg_color`
+    /// the image has premultiplied alpha, which the renderer blends the panel buffers with
+    pub(crate) fn background_image(&self, size: Size<i32, Physical>) -> Option<RgbaImage> {
+        let (w, h) = (size.w.max(0) as u32, size.h.max(0) as u32);
+        let mut image = match &self.config.background {
+            CosmicPanelBackground::Gradient(stops) => {
+                gradient(stops, w, h, self.config.is_horizontal())
+            }
+            CosmicPanelBackground::Image { path, scaling } => {
+                let source = self
+                    .bg_image
+                    .get_or_init(|| match image::open(path) {
+                        Ok(image) => Some(image.into_rgba8()),
+                        Err(err) => {
+                            error!("Failed to load the background image {}: {}", path, err);
+                            None
+                        }
+                    })
+                    .as_ref()?;
+                scaled(source, *scaling, w, h)
+            }
+            _ => return None,
+        };
+
+        let opacity = self.config.opacity.clamp(0.0, 1.0);
+        for pixel in image.pixels_mut() {
+            let a = pixel.0[3] as f32 * opacity / 255.0;
+            pixel.0 = [
+                (pixel.0[0] as f32 * a).round() as u8,
+                (pixel.0[1] as f32 * a).round() as u8,
+                (pixel.0[2] as f32 * a).round() as u8,
+                (a * 255.0).round() as u8,
+            ];
+        }
+        Some(image)
+    }
+}
+
+/// linear gradient along the length of the panel
+fn gradient(stops: &[GradientStop], w: u32, h: u32, is_horizontal: bool) -> RgbaImage {
+    let mut stops = stops
+        .iter()
+        .filter_map(|s| Some((s.position, parse_color(&s.color)?)))
+        .collect::<Vec<_>>();
+    stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let length = if is_horizontal { w } else { h };
+    let colors = (0..length)
+        .map(|i| color_at(&stops, (i as f32 + 0.5) / length as f32))
+        .collect::<Vec<_>>();
+    RgbaImage::from_fn(w, h, |x, y| {
+        let i = if is_horizontal { x } else { y };
+        Rgba(colors[i as usize])
+    })
+}
+
+/// interpolate the color of the sorted stops at the position
+fn color_at(stops: &[(f32, [f32; 4])], position: f32) -> [u8; 4] {
+    let color = match stops.iter().position(|(p, _)| *p >= position) {
+        None => stops.last().map(|s| s.1),
+        Some(0) => stops.first().map(|s| s.1),
+        Some(i) => {
+            let ((p0, c0), (p1, c1)) = (stops[i - 1], stops[i]);
+            let t = if p1 > p0 {
+                (position - p0) / (p1 - p0)
+            } else {
+                0.0
+            };
+            Some(std::array::from_fn(|j| c0[j] + (c1[j] - c0[j]) * t))
+        }
+    }
+    .unwrap_or_default();
+    color.map(|c| (c * 255.0).round().clamp(0.0, 255.0) as u8)
+}
+
+/// fit the image to the panel
+fn scaled(source: &RgbaImage, scaling: ImageScaling, w: u32, h: u32) -> RgbaImage {
+    let filter = imageops::FilterType::Triangle;
+    let (src_w, src_h) = (source.width().max(1) as f64, source.height().max(1) as f64);
+    let resize = |scale: f64| {
+        let (r_w, r_h) = (
+            (src_w * scale).ceil().max(1.0) as u32,
+            (src_h * scale).ceil().max(1.0) as u32,
+        );
+        imageops::resize(source, r_w, r_h, filter)
+    };
+    match scaling {
+        ImageScaling::Stretch => imageops::resize(source, w, h, filter),
+        ImageScaling::Fill => {
+            let resized = resize((w as f64 / src_w).max(h as f64 / src_h));
+            let (x, y) = (
+                resized.width().saturating_sub(w) / 2,
+                resized.height().saturating_sub(h) / 2,
+            );
+            imageops::crop_imm(&resized, x, y, w, h).to_image()
+        }
+        ImageScaling::Fit => centered(&resize((w as f64 / src_w).min(h as f64 / src_h)), w, h),
+        ImageScaling::Center => centered(source, w, h),
+        ImageScaling::Tile => {
+            let mut tiled = RgbaImage::new(w, h);
+            imageops::tile(&mut tiled, source);
+            tiled
+        }
+    }
+}
+
+fn centered(image: &RgbaImage, w: u32, h: u32) -> RgbaImage {
+    let mut centered = RgbaImage::new(w, h);
+    imageops::replace(
+        &mut centered,
+        image,
+        (w as i64 - image.width() as i64) / 2,
+        (h as i64 - image.height() as i64) / 2,
+    );
+    centered
+}
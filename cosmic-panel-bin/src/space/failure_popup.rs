@@ -0,0 +1,219 @@
+//! Popup with the error of a failed applet, which is opened by pressing the indicator of the applet
+//! The popup is a child of the layer surface of the panel, anchored to the indicator, and drawn in a shared memory buffer.
+
+use std::cell::RefCell;
+
+use cosmic_panel_config::PanelAnchor;
+use cosmic_text::{Attrs, Buffer as TextBuffer, Color, FontSystem, Metrics, Shaping, SwashCache};
+use image::{Rgba, RgbaImage};
+use sctk::{
+    compositor::CompositorState,
+    reexports::client::{protocol::wl_shm, QueueHandle},
+    shell::{
+        xdg::{popup::Popup, XdgPositioner, XdgShell},
+        WaylandSurface,
+    },
+    shm::{
+        slot::{Buffer, SlotPool},
+        Shm,
+    },
+};
+use smithay::utils::Rectangle;
+use wayland_protocols::xdg::shell::client::xdg_positioner::{
+    Anchor, ConstraintAdjustment, Gravity,
+};
+use xdg_shell_wrapper::{shared_state::GlobalState, space::WrapperSpace};
+
+use super::PanelSpace;
+
+/// font size of the error, in logical pixels
+const FONT_SIZE: f32 = 14.0;
+const LINE_HEIGHT: f32 = 20.0;
+/// width at which the error wraps, in logical pixels
+const MAX_WIDTH: f32 = 360.0;
+/// space between the error and the edges of the popup, in logical pixels
+const PADDING: i32 = 8;
+
+thread_local! {
+    /// fonts of the popups, which are loaded when the first popup is opened
+    static TEXT: RefCell<Option<(FontSystem, SwashCache)>> = RefCell::new(None);
+}
+
+/// popup with the error of a failed applet
+#[derive(Debug)]
+pub(crate) struct FailurePopup {
+    /// id of the failed applet
+    pub(crate) id: String,
+    // the popup is destroyed before its buffer
+    pub(crate) popup: Popup,
+    buffer: Buffer,
+    _pool: SlotPool,
+}
+
+impl PanelSpace {
+    /// open the popup of the failed applet whose indicator was pressed, replacing an open popup
+    pub(crate) fn show_failure<W: WrapperSpace>(
+        &mut self,
+        compositor_state: &CompositorState,
+        xdg_shell_state: &XdgShell,
+        shm_state: &Shm,
+        qh: &QueueHandle<GlobalState<W>>,
+    ) -> anyhow::Result<()> {
+        let Some(id) = self.requested_failure.take() else {
+            return Ok(());
+        };
+        self.failure_popup = None;
+        let (Some(layer), Some(error), Some(placeholder)) = (
+            self.layer.as_ref(),
+            self.failed_applets.get(&id),
+            self.placeholders.iter().find(|p| p.id == id),
+        ) else {
+            return Ok(());
+        };
+
+        let scale = self.scale.ceil().max(1.0) as i32;
+        let image = self.failure_image(error, scale);
+        let (w, h) = (image.width() as i32, image.height() as i32);
+
+        let mut pool = SlotPool::new((w * h * 4) as usize, shm_state)?;
+        let (buffer, canvas) = pool.create_buffer(w, h, w * 4, wl_shm::Format::Argb8888)?;
+        // argb8888 is stored as bgra in little endian
+        for (pixel, rgba) in canvas.chunks_exact_mut(4).zip(image.pixels()) {
+            let [r, g, b, a] = rgba.0;
+            pixel.copy_from_slice(&[b, g, r, a]);
+        }
+
+        let positioner = XdgPositioner::new(xdg_shell_state)?;
+        positioner.set_size(w / scale, h / scale);
+        let Rectangle { loc, size } = placeholder.rectangle;
+        positioner.set_anchor_rect(loc.x, loc.y, size.w.max(1), size.h.max(1));
+        // the popup opens away from the edge of the panel
+        let (anchor, gravity) = match self.config.anchor {
+            PanelAnchor::Top => (Anchor::Bottom, Gravity::Bottom),
+            PanelAnchor::Bottom => (Anchor::Top, Gravity::Top),
+            PanelAnchor::Left => (Anchor::Right, Gravity::Right),
+            PanelAnchor::Right => (Anchor::Left, Gravity::Left),
+        };
+        positioner.set_anchor(anchor);
+        positioner.set_gravity(gravity);
+        positioner.set_constraint_adjustment(
+            (ConstraintAdjustment::SlideX
+                | ConstraintAdjustment::SlideY
+                | ConstraintAdjustment::FlipX
+                | ConstraintAdjustment::FlipY)
+                .bits(),
+        );
+
+        let c_wl_surface = compositor_state.create_surface(qh);
+        let popup = Popup::from_surface(None, &positioner, qh, c_wl_surface, xdg_shell_state)?;
+        layer.get_popup(popup.xdg_popup());
+        popup.wl_surface().set_buffer_scale(scale);
+        // the buffer is attached once the popup is configured
+        popup.wl_surface().commit();
+
+        self.failure_popup = Some(FailurePopup {
+            id,
+            popup,
+            buffer,
+            _pool: pool,
+        });
+        Ok(())
+    }
+
+    /// draw the popup of a failed applet when it is configured
+    /// returns false if the popup isn't the popup of a failed applet
+    pub(crate) fn configure_failure_popup(&mut self, popup: &Popup) -> bool {
+        let Some(failure_popup) = self
+            .failure_popup
+            .as_ref()
+            .filter(|p| p.popup.wl_surface() == popup.wl_surface())
+        else {
+            return false;
+        };
+        let surface = failure_popup.popup.wl_surface();
+        if failure_popup.buffer.attach_to(surface).is_ok() {
+            surface.damage_buffer(0, 0, i32::MAX, i32::MAX);
+            surface.commit();
+        }
+        true
+    }
+
+    /// close the popup of a failed applet after the compositor dismissed it
+    /// returns false if the popup isn't the popup of a failed applet
+    pub(crate) fn close_failure_popup(&mut self, popup: &Popup) -> bool {
+        if self
+            .failure_popup
+            .as_ref()
+            .map_or(false, |p| p.popup.wl_surface() == popup.wl_surface())
+        {
+            self.failure_popup = None;
+            return true;
+        }
+        false
+    }
+
+    /// draw the error wrapped in a rounded rectangle with the background color of the panel, at the scale of the buffer
+    fn failure_image(&self, error: &str, scale: i32) -> RgbaImage {
+        let s = scale as f32;
+        let padding = (PADDING * scale) as u32;
+        let luma =
+            0.2126 * self.bg_color[0] + 0.7152 * self.bg_color[1] + 0.0722 * self.bg_color[2];
+        let background = self
+            .bg_color
+            .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+        let color = if luma < 0.5 {
+            Color::rgb(255, 255, 255)
+        } else {
+            Color::rgb(0, 0, 0)
+        };
+
+        TEXT.with(|text| {
+            let mut text = text.borrow_mut();
+            let (font_system, swash_cache) =
+                text.get_or_insert_with(|| (FontSystem::new(), SwashCache::new()));
+            let mut buffer =
+                TextBuffer::new(font_system, Metrics::new(FONT_SIZE * s, LINE_HEIGHT * s));
+            let mut buffer = buffer.borrow_with(font_system);
+            buffer.set_size(MAX_WIDTH * s, f32::MAX);
+            buffer.set_text(error, Attrs::new(), Shaping::Advanced);
+            buffer.shape_until_scroll();
+            let (text_w, lines) = buffer.layout_runs().fold((0.0f32, 0), |(w, lines), run| {
+                (w.max(run.line_w), lines + 1)
+            });
+
+            // the size is a multiple of the scale, so it is whole in logical pixels
+            let round = |length: u32| (length + scale as u32 - 1) / scale as u32 * scale as u32;
+            let w = round(text_w.ceil() as u32 + 2 * padding);
+            let h = round((lines as f32 * LINE_HEIGHT * s).ceil() as u32 + 2 * padding);
+            let mut image = RgbaImage::from_pixel(
+                w,
+                h,
+                Rgba([background[0], background[1], background[2], 255]),
+            );
+
+            buffer.draw(swash_cache, color, |x, y, _, _, color| {
+                let (x, y) = (x + padding as i32, y + padding as i32);
+                if x < 0 || y < 0 || x >= w as i32 || y >= h as i32 {
+                    return;
+                }
+                let pixel = image.get_pixel_mut(x as u32, y as u32);
+                let a = color.a() as u32;
+                for (c, glyph) in pixel.0.iter_mut().zip([color.r(), color.g(), color.b()]) {
+                    *c = ((glyph as u32 * a + *c as u32 * (255 - a)) / 255) as u8;
+                }
+            });
+
+            // the popup is opaque so the error stays readable, except for its rounded corners
+            let radius = padding as f64;
+            for (x, y, pixel) in image.enumerate_pixels_mut() {
+                let (cx, cy) = (x as f64 + 0.5, y as f64 + 0.5);
+                let dx = (radius - cx).max(cx - (w as f64 - radius)).max(0.0);
+                let dy = (radius - cy).max(cy - (h as f64 - radius)).max(0.0);
+                if dx * dx + dy * dy > radius * radius {
+                    *pixel = Rgba([0, 0, 0, 0]);
+                }
+            }
+            image
+        })
+    }
+}
@@ -1,15 +1,13 @@
-use crate::space::Alignment;
+use crate::{applet_registry::AppletSection, space::Alignment};
 
-use super::PanelSpace;
-use cosmic_panel_config::PanelAnchor;
+use super::{placeholder::Slot, PanelSpace};
+use cosmic_panel_config::{PanelAnchor, PanelMode};
 use image::RgbaImage;
 use itertools::{chain, Itertools};
 use sctk::shell::WaylandSurface;
-use smithay::utils::{IsAlive, Physical, Size};
+use smithay::utils::{Logical, Physical, Size};
 use smithay::{
     backend::{allocator::Fourcc, renderer::element::memory::MemoryRenderBuffer},
-    desktop::Window,
-    reexports::wayland_server::Resource,
     utils::{Point, Rectangle, Transform},
 };
 
@@ -31,90 +29,46 @@ impl PanelSpace {
                 (self.dimensions.w, self.dimensions.h, self.actual_size.w)
             }
         };
-        let is_dock = !self.config.expand_to_edges();
+        let (is_dock, num_lists) = match &self.config.mode {
+            PanelMode::Bar { wings, center } => (
+                false,
+                wings.as_ref().map_or(0, |_| 2) + center.as_ref().map_or(0, |_| 1),
+            ),
+            // all applets of a dock are in a single centered list
+            PanelMode::Dock { .. } => (true, 1),
+        };
 
-        let mut num_lists = 0;
-        if !is_dock && self.config.plugins_wings.is_some() {
-            num_lists += 2;
-        }
-        if self.config.plugins_center.is_some() {
-            num_lists += 1;
+        // applets without a window keep their space while they restart, or after they failed
+        let mut previous_placeholders = std::mem::take(&mut self.placeholders);
+        let mut windows_right = self.slots(&self.clients_right);
+        let mut windows_center = self.slots(&self.clients_center);
+        let mut windows_left = self.slots(&self.clients_left);
+        if is_dock {
+            // the single list of a dock is ordered by the preferred sections of its applets
+            let registry = self.applet_registry.borrow();
+            windows_center.sort_by_key(|(_, slot, _)| {
+                registry
+                    .get(slot.desktop_id())
+                    .and_then(|e| e.section)
+                    .unwrap_or(AppletSection::Center)
+            });
+            for (i, (index, ..)) in windows_center.iter_mut().enumerate() {
+                *index = i;
+            }
+        } else {
+            self.expand(
+                [&mut windows_left, &mut windows_center, &mut windows_right],
+                list_length,
+                num_lists,
+            );
         }
 
-        let mut windows_right = self
-            .space
-            .elements()
-            .cloned()
-            .filter(|w| w.alive())
-            .filter_map(|w| {
-                self.clients_right
-                    .lock()
-                    .unwrap()
-                    .iter()
-                    .enumerate()
-                    .find_map(|(i, (_, c, _, _))| {
-                        if Some(c.id()) == w.toplevel().wl_surface().client().map(|c| c.id()) {
-                            Some((i, w.clone()))
-                        } else {
-                            None
-                        }
-                    })
-            })
-            .collect_vec();
-        windows_right.sort_by(|(a_i, _), (b_i, _)| a_i.cmp(b_i));
-
-        let mut windows_center = self
-            .space
-            .elements()
-            .cloned()
-            .filter(|w| w.alive())
-            .filter_map(|w| {
-                self.clients_center
-                    .lock()
-                    .unwrap()
-                    .iter()
-                    .enumerate()
-                    .find_map(|(i, (_, c, _, _))| {
-                        if Some(c.id()) == w.toplevel().wl_surface().client().map(|c| c.id()) {
-                            Some((i, w.clone()))
-                        } else {
-                            None
-                        }
-                    })
-            })
-            .collect_vec();
-        windows_center.sort_by(|(a_i, _), (b_i, _)| a_i.cmp(b_i));
-
-        let mut windows_left = self
-            .space
-            .elements()
-            .cloned()
-            .filter(|w| w.alive())
-            .filter_map(|w| {
-                self.clients_left
-                    .lock()
-                    .unwrap()
-                    .iter()
-                    .enumerate()
-                    .find_map(|(i, (_, c, _, _))| {
-                        if Some(c.id()) == w.toplevel().wl_surface().client().map(|c| c.id()) {
-                            Some((i, w.clone()))
-                        } else {
-                            None
-                        }
-                    })
-            })
-            .collect_vec();
-        windows_left.sort_by(|(a_i, _), (b_i, _)| a_i.cmp(b_i));
-
         fn map_fn(
-            (i, w): &(usize, Window),
+            (i, _, bbox): &(usize, Slot, Size<i32, Logical>),
             anchor: PanelAnchor,
             alignment: Alignment,
             _scale: f64,
         ) -> (Alignment, usize, i32, i32) {
-            let bbox = w.bbox().size;
-
             match anchor {
                 PanelAnchor::Left | PanelAnchor::Right => (alignment, *i, bbox.h, bbox.w),
                 PanelAnchor::Top | PanelAnchor::Bottom => (alignment, *i, bbox.w, bbox.h),
@@ -144,11 +98,16 @@ impl PanelSpace {
         let new_list_length = (total_sum_scaled as f64
             + padding_scaled * 2.0
             + spacing_scaled * (num_lists as f64 - 1.0)) as i32;
-        let new_list_thickness = (2.0 * padding_scaled
-            + chain!(left.clone(), center.clone(), right.clone())
-                .map(|(_, _, _, thickness)| thickness)
-                .max()
-                .unwrap_or(0) as f64) as i32;
+        // a custom size fixes the thickness, regardless of the applet sizes
+        let new_list_thickness = if let Some(thickness) = self.config.size.fixed_thickness() {
+            (thickness as f64 * self.scale) as i32
+        } else {
+            (2.0 * padding_scaled
+                + chain!(left.clone(), center.clone(), right.clone())
+                    .map(|(_, _, _, thickness)| thickness)
+                    .max()
+                    .unwrap_or(0) as f64) as i32
+        };
         let old_actual = self.actual_size;
 
         self.actual_size = Size::<i32, Physical>::from(if self.config.is_horizontal() {
@@ -263,10 +222,10 @@ impl PanelSpace {
             PanelAnchor::Bottom | PanelAnchor::Right => 0,
         } as i32;
 
-        for (i, w) in &mut windows_left.iter_mut() {
+        for (i, slot, reserved) in windows_left.iter() {
             // XXX this is a hack to get the logical size of the window
             // TODO improve how this is done
-            let size = w.bbox().size.to_f64().downscale(self.scale);
+            let size = reserved.to_f64().downscale(self.scale);
 
             let cur: f64 = prev + spacing_u32 as f64 * *i as f64;
             match anchor {
@@ -280,8 +239,12 @@ impl PanelSpace {
                         cur,
                     );
                     prev += size.h as f64;
-                    self.space
-                        .map_element(w.clone(), (cur.0 as i32, cur.1 as i32), false);
+                    self.place(
+                        slot,
+                        (cur.0 as i32, cur.1 as i32).into(),
+                        *reserved,
+                        &mut previous_placeholders,
+                    );
                 }
                 PanelAnchor::Top | PanelAnchor::Bottom => {
                     let cur = (
@@ -293,16 +256,20 @@ impl PanelSpace {
                             ),
                     );
                     prev += size.w as f64;
-                    self.space
-                        .map_element(w.clone(), (cur.0 as i32, cur.1 as i32), false);
+                    self.place(
+                        slot,
+                        (cur.0 as i32, cur.1 as i32).into(),
+                        *reserved,
+                        &mut previous_placeholders,
+                    );
                 }
             };
         }
 
         let mut prev: f64 = center_offset;
-        for (i, w) in &mut windows_center.iter_mut() {
+        for (i, slot, reserved) in windows_center.iter() {
             // XXX this is a hack to get the logical size of the window
-            let size = w.bbox().size.to_f64().downscale(self.scale);
+            let size = reserved.to_f64().downscale(self.scale);
 
             let cur = prev + spacing_u32 as f64 * *i as f64;
             match anchor {
@@ -316,8 +283,12 @@ impl PanelSpace {
                         cur,
                     );
                     prev += size.h as f64;
-                    self.space
-                        .map_element(w.clone(), (cur.0 as i32, cur.1 as i32), false);
+                    self.place(
+                        slot,
+                        (cur.0 as i32, cur.1 as i32).into(),
+                        *reserved,
+                        &mut previous_placeholders,
+                    );
                 }
                 PanelAnchor::Top | PanelAnchor::Bottom => {
                     let cur = (
@@ -329,8 +300,12 @@ impl PanelSpace {
                             ),
                     );
                     prev += size.w as f64;
-                    self.space
-                        .map_element(w.clone(), (cur.0 as i32, cur.1 as i32), false);
+                    self.place(
+                        slot,
+                        (cur.0 as i32, cur.1 as i32).into(),
+                        *reserved,
+                        &mut previous_placeholders,
+                    );
                 }
             };
         }
@@ -342,8 +317,8 @@ impl PanelSpace {
             list_length as f64 - padding_u32 as f64 - right_sum
         };
 
-        for (i, w) in &mut windows_right.iter_mut() {
-            let size = w.bbox().size.to_f64().downscale(self.scale);
+        for (i, slot, reserved) in windows_right.iter() {
+            let size = reserved.to_f64().downscale(self.scale);
             let cur = prev + spacing_u32 as f64 * *i as f64;
             match anchor {
                 PanelAnchor::Left | PanelAnchor::Right => {
@@ -356,8 +331,12 @@ impl PanelSpace {
                         cur,
                     );
                     prev += size.h as f64;
-                    self.space
-                        .map_element(w.clone(), (cur.0 as i32, cur.1 as i32), false);
+                    self.place(
+                        slot,
+                        (cur.0 as i32, cur.1 as i32).into(),
+                        *reserved,
+                        &mut previous_placeholders,
+                    );
                 }
                 PanelAnchor::Top | PanelAnchor::Bottom => {
                     let cur = (
@@ -369,16 +348,32 @@ impl PanelSpace {
                             ),
                     );
                     prev += size.w as f64;
-                    self.space
-                        .map_element(w.clone(), (cur.0 as i32, cur.1 as i32), false);
+                    self.place(
+                        slot,
+                        (cur.0 as i32, cur.1 as i32).into(),
+                        *reserved,
+                        &mut previous_placeholders,
+                    );
                 }
             };
         }
+        // the popup of an applet which restarted or was removed is closed
+        if let Some(popup) = self.failure_popup.as_ref() {
+            if !self
+                .placeholders
+                .iter()
+                .any(|p| p.id == popup.id && p.error.is_some())
+            {
+                self.failure_popup = None;
+            }
+        }
         self.space.refresh();
         if self.actual_size.w > 0
             && self.actual_size.h > 0
             && actual_length > 0
-            && (self.config.border_radius > 0 || self.config.get_effective_anchor_gap() > 0)
+            && (self.config.border_radius > 0
+                || self.config.get_effective_anchor_gap() > 0
+                || !self.config.background.is_solid())
         {
             // corners calculation with border_radius
 
@@ -415,16 +410,25 @@ impl PanelSpace {
                 Transform::Normal,
                 None,
             );
+            let background = self.background_image(panel_size);
             let mut render_context = buff.render();
+            // premultiplied alpha, like the background images
+            let alpha = self.bg_color[3];
             let bg_color = self
                 .bg_color
                 .iter()
+                .enumerate()
+                .map(|(i, c)| if i < 3 { c * alpha } else { *c })
                 .map(|c| ((c * 255.0) as u8).clamp(0, 255))
                 .collect_vec();
             let _ = render_context.draw(|buffer| {
-                buffer.chunks_exact_mut(4).for_each(|chunk| {
-                    chunk.copy_from_slice(&bg_color);
-                });
+                if let Some(background) = background.as_ref() {
+                    buffer.copy_from_slice(background.as_raw());
+                } else {
+                    buffer.chunks_exact_mut(4).for_each(|chunk| {
+                        chunk.copy_from_slice(&bg_color);
+                    });
+                }
 
                 let radius = (self.config.border_radius as f64 * self.scale).round() as u32;
                 let radius = radius
@@ -448,13 +452,8 @@ impl PanelSpace {
                     })
                     .collect_vec();
 
-                let bg_color: [u8; 4] = self
-                    .bg_color
-                    .iter()
-                    .map(|c| ((c * 255.0) as u8).clamp(0, 255))
-                    .collect_vec()
-                    .try_into()
-                    .unwrap();
+                // the alpha of the corner image is the coverage of each pixel
+                let covered = [255, 255, 255, 255];
                 let empty = [0, 0, 0, 0];
 
                 let mut corner_image = RgbaImage::new(drawn_radius, drawn_radius);
@@ -469,7 +468,7 @@ impl PanelSpace {
                         && top_left >= 0.0
                         && top_right >= 0.0
                     {
-                        bg_color.clone()
+                        covered
                     } else {
                         empty
                     };
@@ -500,7 +499,9 @@ impl PanelSpace {
                     } {
                         let b_i = (c_y * panel_size.w as u32 + c_x) as usize * 4;
                         let c = buffer.get_mut(b_i..b_i + 4).unwrap();
-                        c.copy_from_slice(&color.0);
+                        let coverage = color.0[3] as u32;
+                        c.iter_mut()
+                            .for_each(|c| *c = (*c as u32 * coverage / 255) as u8);
                     }
                 }
 
@@ -518,4 +519,86 @@ impl PanelSpace {
 
         Ok(())
     }
+
+    /// apply the length hints of the applet to the size of its slot
+    /// the size of an expanding applet with a minimum length starts at that length, so it can shrink again
+    pub(crate) fn hinted_size(
+        &self,
+        slot: &Slot,
+        mut size: Size<i32, Logical>,
+    ) -> Size<i32, Logical> {
+        let registry = self.applet_registry.borrow();
+        let Some(entry) = registry.get(slot.desktop_id()) else {
+            return size;
+        };
+        let scaled = |length: u32| (length as f64 * self.scale).round() as i32;
+        let length = if self.config.is_horizontal() {
+            &mut size.w
+        } else {
+            &mut size.h
+        };
+        if let Some(min) = entry.min_length {
+            *length = if entry.expand {
+                scaled(min)
+            } else {
+                (*length).max(scaled(min))
+            };
+        }
+        if let Some(max) = entry.max_length {
+            *length = (*length).min(scaled(max));
+        }
+        size
+    }
+
+    /// give the free length of a bar to its expanding applets in equal parts, up to their maximum length
+    fn expand(
+        &self,
+        lists: [&mut Vec<(usize, Slot, Size<i32, Logical>)>; 3],
+        list_length: i32,
+        num_lists: usize,
+    ) {
+        let registry = self.applet_registry.borrow();
+        let is_horizontal = self.config.is_horizontal();
+        let length = |size: &Size<i32, Logical>| if is_horizontal { size.w } else { size.h };
+
+        let used: i32 = lists
+            .iter()
+            .flat_map(|list| list.iter())
+            .map(|(_, _, size)| length(size))
+            .sum();
+        let spacings = lists
+            .iter()
+            .map(|list| list.len().max(1) - 1)
+            .sum::<usize>()
+            + num_lists.max(1)
+            - 1;
+        let free = list_length as f64 * self.scale
+            - used as f64
+            - 2.0 * self.config.padding() as f64 * self.scale
+            - spacings as f64 * self.config.spacing() as f64 * self.scale;
+
+        let expanding = lists
+            .iter()
+            .flat_map(|list| list.iter())
+            .filter(|(_, slot, _)| registry.get(slot.desktop_id()).map_or(false, |e| e.expand))
+            .count();
+        if free < 1.0 || expanding == 0 {
+            return;
+        }
+        let share = (free / expanding as f64) as i32;
+        for (_, slot, size) in lists.into_iter().flat_map(|list| list.iter_mut()) {
+            let Some(entry) = registry.get(slot.desktop_id()).filter(|e| e.expand) else {
+                continue;
+            };
+            let max = entry
+                .max_length
+                .map_or(i32::MAX, |max| (max as f64 * self.scale).round() as i32);
+            let length = if is_horizontal {
+                &mut size.w
+            } else {
+                &mut size.h
+            };
+            *length = (*length + share).min(max).max(*length);
+        }
+    }
 }
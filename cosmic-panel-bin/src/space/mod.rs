@@ -1,18 +1,46 @@
 //! PanelSpace is a container for all running panels, spawning each as a separate process and compositing them in a layer shell surface as configured
 //! PanelSpace *partially* implements the WrapperSpace abstraction
 
+mod background;
+mod failure_popup;
 mod layout;
 mod panel_space;
+mod placeholder;
 mod popup;
 mod render;
+mod watchdog;
 mod wrapper_space;
 
 pub(crate) use panel_space::{AppletMsg, PanelSpace};
 pub use wrapper_space::*;
 
+use cosmic_panel_config::OutputMatchInfo;
+use sctk::output::OutputInfo;
+
 #[derive(Debug)]
 pub enum Alignment {
     Left,
     Center,
     Right,
 }
+
+/// get the properties of an output which are matched by the output config of a panel
+pub(crate) fn output_match_info(info: &OutputInfo) -> OutputMatchInfo {
+    // wl_output doesn't have a serial number, but compositors usually put it in the description
+    // after the make and model, for example "Dell Inc. DELL U2720Q ABC1234 (DP-1)"
+    let serial = info.description.as_ref().and_then(|d| {
+        let rest = d.strip_prefix(&info.make)?.trim_start();
+        let rest = rest.strip_prefix(&info.model)?.trim_start();
+        let serial = rest
+            .rsplit_once('(')
+            .map_or(rest, |(serial, _)| serial)
+            .trim();
+        (!serial.is_empty()).then(|| serial.to_string())
+    });
+    OutputMatchInfo {
+        name: info.name.clone().unwrap_or_default(),
+        make: info.make.clone(),
+        model: info.model.clone(),
+        serial,
+    }
+}
@@ -1,5 +1,6 @@
 use std::{
-    cell::{Cell, RefCell},
+    cell::{Cell, OnceCell, RefCell},
+    collections::HashMap,
     os::{fd::OwnedFd, unix::net::UnixStream},
     rc::Rc,
     sync::{Arc, Mutex},
@@ -7,6 +8,7 @@ use std::{
 };
 
 use cctk::wayland_client::Connection;
+use image::RgbaImage;
 use launch_pad::process::Process;
 use sctk::{
     compositor::Region,
@@ -76,14 +78,42 @@ use xdg_shell_wrapper::{
     util::smootherstep,
 };
 
-use cosmic_panel_config::{CosmicPanelBackground, CosmicPanelConfig, PanelAnchor};
+use super::{failure_popup::FailurePopup, placeholder::Placeholder, watchdog::AppletWatch};
+use crate::{applet_registry::AppletRegistry, fd_broker::FdRequest};
+use cosmic_panel_config::{
+    AppletEntry, AppletLogs, CosmicPanelBackground, CosmicPanelConfig, PanelAnchor, RestartPolicy,
+};
 
 pub enum AppletMsg {
-    NewProcess(String, Process),
-    NewNotificationsProcess(String, Process, Vec<(String, String)>, Vec<OwnedFd>),
-    NeedNewNotificationFd(oneshot::Sender<OwnedFd>),
+    /// an applet is started, with the process id, environment, fds, and the fds it gets from brokers
+    NewProcess(
+        String,
+        Process,
+        Vec<(String, String)>,
+        Vec<OwnedFd>,
+        Vec<FdRequest>,
+    ),
+    /// an applet is activated through D-Bus, with the process id, desktop file id, arguments, environment, fds,
+    /// and the fds it gets from brokers
+    Activate(
+        String,
+        String,
+        Vec<String>,
+        Vec<(String, String)>,
+        Vec<OwnedFd>,
+        Vec<FdRequest>,
+    ),
+    /// an applet restarts, and needs new fds from its brokers
+    NeedFds(
+        Vec<FdRequest>,
+        oneshot::Sender<anyhow::Result<Vec<(String, OwnedFd)>>>,
+    ),
     ClientSocketPair(ClientId),
     Cleanup(String),
+    /// the panel exits, so the remaining applets are stopped before the sender is notified
+    Shutdown(std::sync::mpsc::Sender<()>),
+    /// an applet exited too often and was stopped, with the process id and the reason
+    Failed(String, String),
 }
 
 render_elements! {
@@ -115,6 +145,35 @@ pub(crate) struct PanelSpace {
     pub(crate) clients_left: Clients,
     pub(crate) clients_center: Clients,
     pub(crate) clients_right: Clients,
+    // entries the running applets were started with
+    pub(crate) applet_entries: Vec<AppletEntry>,
+    // variables describing the panel, which applets read when they start or restart
+    pub(crate) applet_env: Arc<Mutex<Vec<(String, String)>>>,
+    // id of the panel when its applets were first started, which prefixes their process ids
+    pub(crate) process_id: Option<String>,
+    // desktop entries of the installed applets, shared by all panels
+    pub(crate) applet_registry: Rc<RefCell<AppletRegistry>>,
+    // restart policy of the panel, which running applets read when they exit
+    pub(crate) restart_policy: Arc<Mutex<RestartPolicy>>,
+    // how the output of the applets is logged, which running applets read when they log
+    pub(crate) applet_logs: Arc<Mutex<AppletLogs>>,
+    // name of the output of the panel, for the logs of its applets
+    pub(crate) output_name: Arc<Mutex<String>>,
+    // size of the last window of each applet, which is kept by a placeholder while the applet restarts
+    pub(crate) applet_sizes: HashMap<String, Size<i32, Logical>>,
+    // errors of the applets which exited too often, by instance id
+    pub(crate) failed_applets: HashMap<String, String>,
+    // timings of the running applets, by instance id
+    pub(crate) applet_watches: HashMap<String, AppletWatch>,
+    // when the watchdog restarted each hung applet, by instance id
+    pub(crate) watchdog_restarts: HashMap<String, Vec<Instant>>,
+    pub(crate) placeholders: Vec<Placeholder>,
+    // ids of the failed applets under the pointer of each seat
+    pub(crate) hovered_failures: Vec<(String, String)>,
+    // id of the failed applet whose indicator was pressed, which gets a popup with its error
+    pub(crate) requested_failure: Option<String>,
+    // destroyed before the layer surface it is a child of
+    pub(crate) failure_popup: Option<FailurePopup>,
     pub(crate) last_dirty: Option<Instant>,
     // pending size of the panel
     pub(crate) pending_dimensions: Option<Size<i32, Logical>>,
@@ -139,6 +198,8 @@ pub(crate) struct PanelSpace {
     pub(crate) popups: Vec<WrapperPopup>,
     pub(crate) start_instant: Instant,
     pub(crate) bg_color: [f32; 4],
+    // decoded image of an image background
+    pub(crate) bg_image: OnceCell<Option<RgbaImage>>,
     pub(crate) applet_tx: mpsc::Sender<AppletMsg>,
     pub(crate) input_region: Option<Region>,
     pub(crate) old_buff: Option<MemoryRenderBuffer>,
@@ -158,17 +219,20 @@ impl PanelSpace {
         c_focused_surface: Rc<RefCell<ClientFocus>>,
         c_hovered_surface: Rc<RefCell<ClientFocus>>,
         applet_tx: mpsc::Sender<AppletMsg>,
+        applet_registry: Rc<RefCell<AppletRegistry>>,
         mut bg_color: [f32; 4],
         s_display: DisplayHandle,
         security_context_manager: Option<SecurityContextManager>,
         conn: &Connection,
     ) -> Self {
-        bg_color[3] = config.opacity;
+        bg_color[3] = config.background_alpha();
         let visibility = if config.autohide.is_none() {
             Visibility::Visible
         } else {
             Visibility::Hidden
         };
+        let restart_policy = Arc::new(Mutex::new(config.restart_policy.clone()));
+        let applet_logs = Arc::new(Mutex::new(config.applet_logs.clone()));
 
         Self {
             config,
@@ -176,6 +240,21 @@ impl PanelSpace {
             clients_left: Default::default(),
             clients_center: Default::default(),
             clients_right: Default::default(),
+            applet_entries: Vec::new(),
+            applet_env: Default::default(),
+            process_id: None,
+            applet_registry,
+            restart_policy,
+            applet_logs,
+            output_name: Default::default(),
+            applet_sizes: HashMap::new(),
+            failed_applets: HashMap::new(),
+            applet_watches: HashMap::new(),
+            watchdog_restarts: HashMap::new(),
+            placeholders: Vec::new(),
+            hovered_failures: Vec::new(),
+            requested_failure: None,
+            failure_popup: None,
             last_dirty: Default::default(),
             pending_dimensions: Default::default(),
             space_event: Default::default(),
@@ -196,6 +275,7 @@ impl PanelSpace {
             s_focused_surface: Default::default(),
             s_hovered_surface: Default::default(),
             bg_color,
+            bg_image: OnceCell::new(),
             applet_tx,
             actual_size: (0, 0).into(),
             input_region: None,
@@ -502,6 +582,7 @@ impl PanelSpace {
     ) -> Instant {
         self.space.refresh();
         popup_manager.cleanup();
+        self.check_applets(qh);
 
         self.handle_focus();
         let mut should_render = false;
@@ -860,6 +941,7 @@ impl PanelSpace {
 impl Drop for PanelSpace {
     fn drop(&mut self) {
         // request processes to stop
-        let _ = self.applet_tx.try_send(AppletMsg::Cleanup(self.id()));
+        let id = self.process_id.clone().unwrap_or_else(|| self.id());
+        let _ = self.applet_tx.try_send(AppletMsg::Cleanup(id));
     }
 }
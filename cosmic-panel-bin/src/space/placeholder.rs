@@ -0,0 +1,221 @@
+//! Placeholders which keep the space of applets without a window, while they restart or after they failed
+
+use cosmic_panel_config::desktop_id;
+use image::{Rgba, RgbaImage};
+use smithay::{
+    backend::{allocator::Fourcc, renderer::element::memory::MemoryRenderBuffer},
+    desktop::Window,
+    reexports::wayland_server::Resource,
+    utils::{IsAlive, Logical, Physical, Point, Rectangle, Size, Transform},
+};
+
+use super::{panel_space::Clients, PanelSpace};
+
+/// color of the failed indicator
+const FAILED_COLOR: [u8; 4] = [204, 51, 51, 230];
+
+/// an applet in one of the lists of the panel
+#[derive(Debug, Clone)]
+pub(crate) enum Slot {
+    /// the window of the applet with the instance id
+    Window(String, Window),
+    /// the applet with the instance id is restarting, or failed
+    Placeholder(String),
+}
+
+impl Slot {
+    /// get the instance id of the applet
+    pub(crate) fn id(&self) -> &str {
+        match self {
+            Slot::Window(id, _) | Slot::Placeholder(id) => id,
+        }
+    }
+
+    /// get the desktop file id of the applet
+    pub(crate) fn desktop_id(&self) -> &str {
+        desktop_id(self.id())
+    }
+}
+
+/// space reserved for an applet without a window
+#[derive(Debug)]
+pub(crate) struct Placeholder {
+    /// instance id of the applet
+    pub(crate) id: String,
+    /// location and size of the placeholder in the panel
+    pub(crate) rectangle: Rectangle<i32, Logical>,
+    /// error of a failed applet
+    pub(crate) error: Option<String>,
+    pub(crate) buffer: MemoryRenderBuffer,
+}
+
+impl PanelSpace {
+    /// get the applets of the list in order, with the size reserved for them
+    /// applets without a window are left out, unless they have a placeholder
+    pub(crate) fn slots(&self, clients: &Clients) -> Vec<(usize, Slot, Size<i32, Logical>)> {
+        let windows: Vec<_> = self.space.elements().filter(|w| w.alive()).collect();
+        clients
+            .lock()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (id, c, _, _))| {
+                let window = windows
+                    .iter()
+                    .find(|w| Some(c.id()) == w.toplevel().wl_surface().client().map(|c| c.id()));
+                match window {
+                    Some(w) => Some((i, Slot::Window(id.clone(), (*w).clone()), w.bbox().size)),
+                    None => Some((i, Slot::Placeholder(id.clone()), self.placeholder_size(id)?)),
+                }
+            })
+            .map(|(i, slot, size)| {
+                let size = self.hinted_size(&slot, size);
+                (i, slot, size)
+            })
+            .collect()
+    }
+
+    /// map the window of the slot centered in the size reserved for it, or add a placeholder for it
+    /// placeholders which look the same as before keep their buffer
+    pub(crate) fn place(
+        &mut self,
+        slot: &Slot,
+        loc: Point<i32, Logical>,
+        reserved: Size<i32, Logical>,
+        previous: &mut Vec<Placeholder>,
+    ) {
+        let id = match slot {
+            Slot::Window(id, w) => {
+                let size = w.bbox().size;
+                self.applet_sizes.insert(id.clone(), size);
+                // applets with length hints are asked to fill the length reserved for them
+                let has_length_hints = self
+                    .applet_registry
+                    .borrow()
+                    .get(desktop_id(id))
+                    .map_or(false, |e| e.has_length_hints());
+                if has_length_hints && reserved != size {
+                    let reserved = reserved.to_f64().downscale(self.scale).to_i32_round();
+                    w.toplevel().with_pending_state(|state| {
+                        state.size = Some(reserved);
+                    });
+                    w.toplevel().send_pending_configure();
+                }
+                let offset = Point::<f64, Logical>::from((
+                    (reserved.w - size.w).max(0) as f64 / 2.0,
+                    (reserved.h - size.h).max(0) as f64 / 2.0,
+                ))
+                .downscale(self.scale)
+                .to_i32_round();
+                self.space.map_element(w.clone(), loc + offset, false);
+                return;
+            }
+            Slot::Placeholder(id) => id,
+        };
+        let Some(size) = self.placeholder_size(id) else {
+            return;
+        };
+        let rectangle =
+            Rectangle::from_loc_and_size(loc, size.to_f64().downscale(self.scale).to_i32_round());
+        let error = self.failed_applets.get(id).cloned();
+        let placeholder = match previous
+            .iter()
+            .position(|p| p.id == *id && p.rectangle.size == rectangle.size && p.error == error)
+        {
+            Some(i) => {
+                let mut placeholder = previous.remove(i);
+                placeholder.rectangle = rectangle;
+                placeholder
+            }
+            None => {
+                let image = self.placeholder_image((size.w, size.h).into(), error.is_some());
+                let mut buffer = MemoryRenderBuffer::new(
+                    Fourcc::Abgr8888,
+                    (size.w, size.h),
+                    1,
+                    Transform::Normal,
+                    None,
+                );
+                let mut render_context = buffer.render();
+                let _ = render_context.draw(|buffer| {
+                    buffer.copy_from_slice(image.as_raw());
+                    Result::<_, ()>::Ok(vec![Rectangle::from_loc_and_size(
+                        Point::default(),
+                        (size.w, size.h),
+                    )])
+                });
+                drop(render_context);
+                Placeholder {
+                    id: id.clone(),
+                    rectangle,
+                    error,
+                    buffer,
+                }
+            }
+        };
+        self.placeholders.push(placeholder);
+    }
+
+    /// get the size reserved for an applet without a window
+    /// a restarting applet keeps the size of its last window, and a failed applet without one gets a square
+    pub(crate) fn placeholder_size(&self, id: &str) -> Option<Size<i32, Logical>> {
+        if let Some(size) = self.applet_sizes.get(id) {
+            return Some(*size);
+        }
+        if !self.failed_applets.contains_key(id) {
+            return None;
+        }
+        let side = (self.config.get_applet_icon_size() + 2 * self.config.padding()) as f64;
+        let side = (side * self.scale).round() as i32;
+        Some((side, side).into())
+    }
+
+    /// get the id of the failed applet at the location in the panel
+    pub(crate) fn failed_applet_at(&self, loc: Point<f64, Logical>) -> Option<&str> {
+        self.placeholders
+            .iter()
+            .find(|p| p.error.is_some() && p.rectangle.to_f64().contains(loc))
+            .map(|p| p.id.as_str())
+    }
+
+    /// draw a rounded rectangle tinted with the background, with an exclamation mark for failed applets
+    fn placeholder_image(&self, size: Size<i32, Physical>, failed: bool) -> RgbaImage {
+        let (w, h) = (size.w.max(1) as u32, size.h.max(1) as u32);
+        let luma =
+            0.2126 * self.bg_color[0] + 0.7152 * self.bg_color[1] + 0.0722 * self.bg_color[2];
+        let fill = if failed {
+            FAILED_COLOR
+        } else if luma < 0.5 {
+            [255, 255, 255, 40]
+        } else {
+            [0, 0, 0, 40]
+        };
+        let radius = (w.min(h) / 4) as f64;
+        // the mark is a bar above a dot, centered in the placeholder
+        let mark_width = (w.min(h) / 8).max(1);
+        let mark_x = (w - mark_width) / 2..(w - mark_width) / 2 + mark_width;
+        let bar_y = h / 4..h * 3 / 5;
+        let dot_y = h * 2 / 3..h * 2 / 3 + mark_width;
+
+        RgbaImage::from_fn(w, h, |x, y| {
+            // distance to the center of the nearest corner, if the pixel is in a corner
+            let (cx, cy) = (x as f64 + 0.5, y as f64 + 0.5);
+            let dx = (radius - cx).max(cx - (w as f64 - radius)).max(0.0);
+            let dy = (radius - cy).max(cy - (h as f64 - radius)).max(0.0);
+            if dx * dx + dy * dy > radius * radius {
+                return Rgba([0, 0, 0, 0]);
+            }
+            if failed && mark_x.contains(&x) && (bar_y.contains(&y) || dot_y.contains(&y)) {
+                return Rgba([255, 255, 255, 255]);
+            }
+            // premultiplied alpha, like the background of the panel
+            let a = fill[3] as u32;
+            Rgba([
+                (fill[0] as u32 * a / 255) as u8,
+                (fill[1] as u32 * a / 255) as u8,
+                (fill[2] as u32 * a / 255) as u8,
+                fill[3],
+            ])
+        })
+    }
+}
@@ -39,7 +39,7 @@ impl PanelSpace {
             };
             renderer.unbind()?;
             renderer.bind(self.egl_surface.as_ref().unwrap().clone())?;
-            let is_dock = !self.config.expand_to_edges();
+            let is_dock = self.config.is_dock();
             let clear_color = if self.buffer.is_none() {
                 &self.bg_color
             } else {
@@ -108,6 +108,24 @@ impl PanelSpace {
                     .flatten()
                     .collect_vec();
 
+                for p in &self.placeholders {
+                    if let Ok(render_element) = MemoryRenderBufferRenderElement::from_buffer(
+                        renderer,
+                        p.rectangle
+                            .loc
+                            .to_f64()
+                            .to_physical(self.scale)
+                            .to_i32_round(),
+                        &p.buffer,
+                        None,
+                        None,
+                        None,
+                        smithay::backend::renderer::element::Kind::Unspecified,
+                    ) {
+                        elements.push(MyRenderElements::Memory(render_element));
+                    }
+                }
+
                 // FIXME the first draw is stretched even when not scaled when using a buffer
                 // this is a workaround
                 if !self.first_draw {
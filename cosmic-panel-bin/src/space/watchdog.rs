@@ -0,0 +1,285 @@
+//! Watchdog of the applets of a panel
+//! An applet has to map its window before its startup deadline, and answer the pings of the embedded compositor if it is pinged.
+//! Applets which don't are stopped through the process manager and started again, or stopped and shown as failed.
+
+use std::time::Instant;
+
+use cosmic_panel_config::{RestartPolicy, Watchdog, WatchdogAction};
+use sctk::reexports::client::QueueHandle;
+use smithay::{
+    desktop::Window,
+    reexports::wayland_server::{Client, Resource},
+    utils::SERIAL_COUNTER,
+    wayland::shell::xdg::PingError,
+};
+use tracing::{error, info, warn};
+use xdg_shell_wrapper::{shared_state::GlobalState, space::WrapperSpace};
+
+use crate::applet_registry::AppletLaunch;
+
+use super::{AppletMsg, PanelSpace};
+
+/// Timings of an applet since it was started
+#[derive(Debug)]
+pub(crate) struct AppletWatch {
+    /// client the applet was started with, which is replaced when it restarts
+    client: Client,
+    started: Instant,
+    /// when the applet mapped its first window
+    mapped: Option<Instant>,
+    /// when the last ping was sent
+    pinged: Option<Instant>,
+    /// whether the watchdog acted on the applet, which is left alone until it restarts
+    hung: bool,
+}
+
+impl PanelSpace {
+    /// get the watchdog of the applet, or the watchdog of the panel
+    fn watchdog(&self, id: &str) -> &Watchdog {
+        self.applet_entries
+            .iter()
+            .find(|e| e.instance_id() == id)
+            .and_then(|e| e.watchdog())
+            .unwrap_or(&self.config.watchdog)
+    }
+
+    /// get the restart policy of the applet, or the restart policy of the panel
+    fn applet_restart_policy(&self, id: &str) -> RestartPolicy {
+        self.applet_entries
+            .iter()
+            .find(|e| e.instance_id() == id)
+            .and_then(|e| e.restart_policy())
+            .cloned()
+            .unwrap_or_else(|| self.restart_policy.lock().unwrap().clone())
+    }
+
+    /// whether the applet is activated through D-Bus, which owns its process
+    fn is_activated(&self, id: &str) -> bool {
+        let registry = self.applet_registry.borrow();
+        self.applet_entries
+            .iter()
+            .find(|e| e.instance_id() == id)
+            .and_then(|e| registry.get(e.id()))
+            .map_or(false, |entry| entry.launch == AppletLaunch::DBus)
+    }
+
+    /// watch the applets which were started or restarted, and forget the removed applets
+    fn update_watches(&mut self) {
+        let clients: Vec<_> = [
+            &self.clients_left,
+            &self.clients_center,
+            &self.clients_right,
+        ]
+        .iter()
+        .flat_map(|clients| {
+            clients
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(id, c, ..)| (id.clone(), c.clone()))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+        self.applet_watches
+            .retain(|id, _| clients.iter().any(|(c_id, _)| c_id == id));
+        self.watchdog_restarts
+            .retain(|id, _| self.applet_entries.iter().any(|e| e.instance_id() == *id));
+        let now = Instant::now();
+        for (id, client) in clients {
+            if self
+                .applet_watches
+                .get(&id)
+                .map_or(false, |watch| watch.client.id() == client.id())
+            {
+                continue;
+            }
+            self.applet_watches.insert(
+                id,
+                AppletWatch {
+                    client,
+                    started: now,
+                    mapped: None,
+                    pinged: None,
+                    hung: false,
+                },
+            );
+        }
+    }
+
+    /// record when the applet of the window mapped its first window, and log the time since its start
+    pub(crate) fn watch_mapped(&mut self, w: &Window) {
+        self.update_watches();
+        let Some(client) = w.toplevel().wl_surface().client() else {
+            return;
+        };
+        let Some((id, watch)) = self
+            .applet_watches
+            .iter_mut()
+            .find(|(_, watch)| watch.client.id() == client.id())
+        else {
+            return;
+        };
+        if watch.mapped.is_none() {
+            let now = Instant::now();
+            info!(
+                "{} of {} mapped its window {}ms after it was started",
+                id,
+                self.config.name,
+                now.duration_since(watch.started).as_millis()
+            );
+            watch.mapped = Some(now);
+        }
+    }
+
+    /// check the startup deadlines of the applets, and ping their windows
+    pub(crate) fn check_applets<W: WrapperSpace>(&mut self, qh: &QueueHandle<GlobalState<W>>) {
+        self.update_watches();
+        let now = Instant::now();
+        let mut hung = Vec::new();
+        let ids: Vec<_> = self.applet_watches.keys().cloned().collect();
+        for id in ids {
+            if self.failed_applets.contains_key(&id) {
+                continue;
+            }
+            let watchdog = self.watchdog(&id).clone();
+            let Some(watch) = self.applet_watches.get_mut(&id) else {
+                continue;
+            };
+            if watch.hung {
+                continue;
+            }
+            if watch.mapped.is_none() {
+                if let Some(timeout) = watchdog
+                    .startup_timeout()
+                    .filter(|timeout| now.duration_since(watch.started) >= *timeout)
+                {
+                    hung.push((
+                        id.clone(),
+                        format!(
+                            "{} didn't map a window within {}ms of its start",
+                            id,
+                            timeout.as_millis()
+                        ),
+                    ));
+                }
+                continue;
+            }
+
+            let Some(interval) = watchdog.ping_interval() else {
+                continue;
+            };
+            if watch
+                .pinged
+                .map_or(false, |pinged| now.duration_since(pinged) < interval)
+            {
+                continue;
+            }
+            let Some(window) = self.space.elements().find(|w| {
+                w.toplevel().wl_surface().client().map(|c| c.id()) == Some(watch.client.id())
+            }) else {
+                continue;
+            };
+            // a ping which wasn't answered is still pending when the next one is due
+            match window
+                .toplevel()
+                .client()
+                .send_ping(SERIAL_COUNTER.next_serial())
+            {
+                Ok(()) => watch.pinged = Some(now),
+                Err(PingError::PingAlreadyPending(_)) => hung.push((
+                    id.clone(),
+                    format!(
+                        "{} didn't answer a ping within {}ms, {}ms after it was started",
+                        id,
+                        interval.as_millis(),
+                        now.duration_since(watch.started).as_millis()
+                    ),
+                )),
+                Err(_) => {}
+            }
+        }
+
+        let mut restarted = false;
+        for (id, reason) in hung {
+            let action = self.watchdog(&id).action;
+            let Some(watch) = self.applet_watches.get_mut(&id) else {
+                continue;
+            };
+            watch.hung = true;
+            let client = watch.client.clone();
+            let reason = match action {
+                // the bus owns the process of the applet, so the panel can't stop it
+                WatchdogAction::Restart if self.is_activated(&id) => format!(
+                    "{}, and it can't be restarted since it is activated through D-Bus",
+                    reason
+                ),
+                WatchdogAction::Restart => {
+                    // hangs within the cooldown window count towards the restart limit
+                    let restart_policy = self.applet_restart_policy(&id);
+                    let restarts = self.watchdog_restarts.entry(id.clone()).or_default();
+                    let now = Instant::now();
+                    restarts
+                        .retain(|restart| now.duration_since(*restart) < restart_policy.cooldown());
+                    restarts.push(now);
+                    let restarts = restarts.len() as u32;
+                    if restarts > restart_policy.max_restarts {
+                        format!(
+                            "{}, it hung {} times within {}ms",
+                            reason, restarts, restart_policy.cooldown
+                        )
+                    } else {
+                        warn!("{}, restarting it", reason);
+                        self.stop_hung(&id, &client);
+                        restarted = true;
+                        continue;
+                    }
+                }
+                WatchdogAction::Fail => reason,
+            };
+            error!("{}, stopping it", reason);
+            let panel_id = self.process_id.clone().unwrap_or_default();
+            let msg = AppletMsg::Failed(format!("{}/{}", panel_id, id), reason);
+            if let Err(e) = self.applet_tx.try_send(msg) {
+                error!("{e}");
+            }
+        }
+
+        // the stopped applets aren't in the client lists anymore, so they are started again
+        if restarted {
+            if let Some(s_display) = self.s_display.clone() {
+                if let Err(err) =
+                    self.update_clients(s_display, qh, self.security_context_manager.clone())
+                {
+                    error!("Failed to restart the hung applets: {:?}", err);
+                }
+            }
+        }
+    }
+
+    /// stop the processes of the hung applet through the process manager, and forget its client and windows
+    fn stop_hung(&mut self, id: &str, client: &Client) {
+        for clients in [
+            &self.clients_left,
+            &self.clients_center,
+            &self.clients_right,
+        ] {
+            clients.lock().unwrap().retain(|(c_id, ..)| c_id != id);
+        }
+        let windows: Vec<_> = self
+            .space
+            .elements()
+            .filter(|w| w.toplevel().wl_surface().client().map(|c| c.id()) == Some(client.id()))
+            .cloned()
+            .collect();
+        for w in windows {
+            self.space.unmap_elem(&w);
+        }
+        let panel_id = self.process_id.clone().unwrap_or_default();
+        if let Err(e) = self
+            .applet_tx
+            .try_send(AppletMsg::Cleanup(format!("{}/{}", panel_id, id)))
+        {
+            error!("{e}");
+        }
+    }
+}
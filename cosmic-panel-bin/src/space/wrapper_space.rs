@@ -1,15 +1,17 @@
 use std::{
-    cell::{Cell, RefCell},
-    ffi::OsString,
-    fs,
-    os::{fd::OwnedFd, unix::prelude::AsRawFd},
+    cell::{Cell, OnceCell, RefCell},
+    mem,
+    os::{
+        fd::OwnedFd,
+        unix::{net::UnixStream, prelude::AsRawFd},
+    },
     rc::Rc,
+    sync::{Arc, Mutex},
     time::Instant,
 };
 
 use anyhow::bail;
-use cosmic_panel_config::{CosmicPanelConfig, CosmicPanelOuput, NAME};
-use freedesktop_desktop_entry::{self, DesktopEntry, Iter};
+use cosmic_panel_config::{AppletEntry, ChangeKind, CosmicPanelConfig, CosmicPanelOuput, NAME};
 use itertools::izip;
 use launch_pad::process::Process;
 use sctk::{
@@ -27,13 +29,12 @@ use sctk::{
         WaylandSurface,
     },
 };
-use shlex::Shlex;
 use smithay::{
     backend::renderer::{damage::OutputDamageTracker, gles::GlesRenderer},
     desktop::{utils::bbox_from_surface_tree, PopupKind, PopupManager, Window},
     output::Output,
     reexports::wayland_server::{
-        self, protocol::wl_surface::WlSurface as s_WlSurface, DisplayHandle, Resource,
+        self, protocol::wl_surface::WlSurface as s_WlSurface, Client, DisplayHandle, Resource,
     },
     utils::{Logical, Rectangle, Size},
     wayland::{
@@ -44,7 +45,8 @@ use smithay::{
 };
 use smithay::{desktop::space::SpaceElement, wayland::fractional_scale::with_fractional_scale};
 use tokio::sync::oneshot;
-use tracing::{error, error_span, info, info_span, trace};
+use tracing::{error, info, trace, warn};
+use wayland_protocols::wp::security_context::v1::client::wp_security_context_v1::WpSecurityContextV1;
 use wayland_protocols_wlr::layer_shell::v1::client::zwlr_layer_shell_v1;
 use xdg_shell_wrapper::{
     client_state::ClientFocus,
@@ -57,9 +59,14 @@ use xdg_shell_wrapper::{
     wp_viewporter::ViewporterState,
 };
 
-use crate::space::AppletMsg;
+use crate::{
+    applet_log::{AppletLog, Stream},
+    applet_registry::AppletLaunch,
+    resource_limits::limited_command,
+    space::{output_match_info, AppletMsg},
+};
 
-use super::PanelSpace;
+use super::{panel_space::Clients, PanelSpace};
 
 impl WrapperSpace for PanelSpace {
     type Config = CosmicPanelConfig;
@@ -88,6 +95,7 @@ impl WrapperSpace for PanelSpace {
                 });
             });
         }
+        self.watch_mapped(&w);
         self.space.map_element(w.clone(), (0, 0), false);
     }
 
@@ -240,358 +248,24 @@ impl WrapperSpace for PanelSpace {
 
     fn spawn_clients<W: WrapperSpace>(
         &mut self,
-        mut display: DisplayHandle,
+        display: DisplayHandle,
         qh: &QueueHandle<GlobalState<W>>,
         security_context_manager: Option<SecurityContextManager>,
     ) -> anyhow::Result<()> {
-        info!("Spawning applets");
-        let mut left_guard = self.clients_left.lock().unwrap();
-        let mut center_guard = self.clients_center.lock().unwrap();
-        let mut right_guard = self.clients_right.lock().unwrap();
-
-        if left_guard.is_empty() && center_guard.is_empty() && right_guard.is_empty() {
-            *left_guard = self
-                .config
-                .plugins_left()
-                .as_ref()
-                .cloned()
-                .unwrap_or_default()
-                .into_iter()
-                .map(|id| {
-                    let (c, s) = get_client_sock(&mut display);
-                    (id, c, Some(s), None)
-                })
-                .collect();
-
-            *center_guard = self
-                .config
-                .plugins_center()
-                .as_ref()
-                .cloned()
-                .unwrap_or_default()
-                .into_iter()
-                .map(|id| {
-                    let (c, s) = get_client_sock(&mut display);
-                    (id, c, Some(s), None)
-                })
-                .collect();
-
-            *right_guard = self
-                .config
-                .plugins_right()
-                .as_ref()
-                .cloned()
-                .unwrap_or_default()
-                .into_iter()
-                .map(|id| {
-                    let (c, s) = get_client_sock(&mut display);
-                    (id, c, Some(s), None)
-                })
-                .collect();
-
-            let mut desktop_ids: Vec<_> = left_guard
-                .iter_mut()
-                .map(|(a, b, c, d)| (a, b, c, d, self.clients_left.clone()))
-                .chain(
-                    center_guard
-                        .iter_mut()
-                        .map(|(a, b, c, d)| (a, b, c, d, self.clients_center.clone())),
-                )
-                .chain(
-                    right_guard
-                        .iter_mut()
-                        .map(|(a, b, c, d)| (a, b, c, d, self.clients_right.clone())),
-                )
-                .collect();
-
-            let config_size = ron::ser::to_string(&self.config.size).unwrap_or_default();
-            let active_output = self
-                .output
-                .as_ref()
-                .and_then(|o| o.2.name.clone())
-                .unwrap_or_default();
-
-            let config_anchor = ron::ser::to_string(&self.config.anchor).unwrap_or_default();
-            let config_bg = ron::ser::to_string(&self.config.background).unwrap_or_default();
-            let env_vars = vec![
-                ("COSMIC_PANEL_SIZE".to_string(), config_size),
-                ("COSMIC_PANEL_OUTPUT".to_string(), active_output),
-                ("COSMIC_PANEL_ANCHOR".to_string(), config_anchor),
-                ("COSMIC_PANEL_BACKGROUND".to_string(), config_bg),
-                ("RUST_BACKTRACE".to_string(), "1".to_string()),
-            ];
-            info!("{:?}", &desktop_ids);
-
-            for path in Iter::new(freedesktop_desktop_entry::default_paths()) {
-                if let Some(position) = desktop_ids.iter().position(|(app_file_name, ..)| {
-                    Some(OsString::from(app_file_name).as_os_str()) == path.file_stem()
-                }) {
-                    // This way each applet is at most started once,
-                    // even if multiple desktop files in different directories match
-                    let (id, client, socket, applet_security_context, my_list) =
-                        desktop_ids.remove(position);
-                    info!(id);
-
-                    let Some(socket) = socket.take() else {
-                        error!("Failed to get socket for {}", &id);
-                        continue;
-                    };
-
-                    if let Ok(bytes) = fs::read_to_string(&path) {
-                        if let Ok(entry) = DesktopEntry::decode(&path, &bytes) {
-                            if let Some(exec) = entry.exec() {
-                                info!("Starting: {}", exec);
-
-                                let requests_wayland_display =
-                                    entry.desktop_entry("X-HostWaylandDisplay").is_some();
-
-                                let mut exec_iter = Shlex::new(exec);
-                                let exec = exec_iter
-                                    .next()
-                                    .expect("exec parameter must contain at least on word");
-
-                                let mut args = Vec::new();
-                                for arg in exec_iter {
-                                    trace!("child argument: {}", &arg);
-                                    args.push(arg);
-                                }
-                                let mut fds = Vec::with_capacity(2);
-                                let mut applet_env = Vec::new();
-
-                                if requests_wayland_display {
-                                    if let Some(security_context_manager) =
-                                        security_context_manager.as_ref()
-                                    {
-                                        match security_context_manager.create_listener::<W>(qh) {
-                                            Ok(security_context) => {
-                                                security_context
-                                                    .set_sandbox_engine(NAME.to_string());
-                                                security_context.commit();
-
-                                                let data = security_context
-                                                    .data::<SecurityContext>()
-                                                    .unwrap();
-                                                let privileged_socket =
-                                                    data.conn.lock().unwrap().take().unwrap();
-                                                applet_env.push((
-                                                    "X_PRIVILEGED_WAYLAND_SOCKET".to_string(),
-                                                    privileged_socket.as_raw_fd().to_string(),
-                                                ));
-                                                fds.push(privileged_socket.into());
-                                                *applet_security_context = Some(security_context);
-                                            }
-                                            Err(why) => {
-                                                error!(?why, "Failed to create a listener");
-                                            }
-                                        }
-                                    };
-                                }
-
-                                for (key, val) in &env_vars {
-                                    if !requests_wayland_display && *key == "WAYLAND_DISPLAY" {
-                                        continue;
-                                    }
-                                    applet_env.push((key.clone(), val.clone()));
-                                }
-                                applet_env.push((
-                                    "WAYLAND_SOCKET".to_string(),
-                                    socket.as_raw_fd().to_string(),
-                                ));
-
-                                fds.push(socket.into());
-                                trace!("child: {}, {:?} {:?}", &exec, args, applet_env);
-                                let is_notification_applet =
-                                    entry.desktop_entry("X-NotificationsApplet").is_some();
-
-                                let display_handle = display.clone();
-                                let applet_tx_clone = self.applet_tx.clone();
-                                let id_clone = id.clone();
-                                let id_clone_info = id.clone();
-                                let id_clone_err = id.clone();
-                                let client_id = client.id();
-                                let client_id_info = client.id();
-                                let client_id_err = client.id();
-                                let security_context_manager_clone =
-                                    security_context_manager.clone();
-                                let qh_clone = qh.clone();
-
-                                let mut process = Process::new()
-                                    .with_executable(&exec)
-                                    .with_args(args)
-                                    .with_on_stderr(move |_, _, out| {
-                                        // TODO why is span not included in logs to journald
-                                        let id_clone = id_clone_err.clone();
-                                        let client_id = client_id_err.clone();
-
-                                        async move {
-                                            error_span!("stderr", client = ?client_id).in_scope(
-                                                || {
-                                                    error!("{}: {}", id_clone, out);
-                                                },
-                                            );
-                                        }
-                                    })
-                                    .with_on_stdout(move |_, _, out| {
-                                        let id_clone = id_clone_info.clone();
-                                        let client_id = client_id_info.clone();
-                                        // TODO why is span not included in logs to journald
-                                        async move {
-                                            info_span!("stdout", client = ?client_id).in_scope(
-                                                || {
-                                                    info!("{}: {}", id_clone, out);
-                                                },
-                                            );
-                                        }
-                                    })
-                                    .with_on_exit(move |mut pman, key, err_code, is_restarting| {
-                                        let my_list = my_list.clone();
-                                        let mut display_handle = display_handle.clone();
-                                        let id_clone = id_clone.clone();
-                                        let applet_tx_clone = applet_tx_clone.clone();
-                                        let (c, client_socket) =
-                                            get_client_sock(&mut display_handle);
-                                        let raw_client_socket = client_socket.as_raw_fd();
-                                        let client_id_clone = client_id.clone();
-                                        let mut applet_env = Vec::with_capacity(1);
-                                        let mut fds: Vec<OwnedFd> = Vec::with_capacity(2);
-                                        let security_context = if requests_wayland_display {
-                                            security_context_manager_clone.as_ref().and_then(
-                                                |security_context_manager| {
-                                                    security_context_manager
-                                                        .create_listener(&qh_clone)
-                                                        .ok()
-                                                        .map(|security_context| {
-                                                            security_context.set_sandbox_engine(
-                                                                NAME.to_string(),
-                                                            );
-                                                            security_context.commit();
-
-                                                            let data = security_context
-                                                                .data::<SecurityContext>()
-                                                                .unwrap();
-                                                            let privileged_socket = data
-                                                                .conn
-                                                                .lock()
-                                                                .unwrap()
-                                                                .take()
-                                                                .unwrap();
-                                                            applet_env.push((
-                                                                "X_PRIVILEGED_WAYLAND_SOCKET"
-                                                                    .to_string(),
-                                                                privileged_socket
-                                                                    .as_raw_fd()
-                                                                    .to_string(),
-                                                            ));
-                                                            fds.push(privileged_socket.into());
-                                                            security_context
-                                                        })
-                                                },
-                                            )
-                                        } else {
-                                            None
-                                        };
-
-                                        async move {
-                                            if let Some(err_code) = err_code {
-                                                error!("Exited with error code {}", err_code)
-                                            }
-                                            if !is_restarting {
-                                                return;
-                                            }
-
-                                            if is_notification_applet {
-                                                let (tx, rx) = oneshot::channel();
-                                                _ = applet_tx_clone
-                                                    .send(AppletMsg::NeedNewNotificationFd(tx))
-                                                    .await;
-                                                let Ok(fd) = rx.await else {
-                                                    error!("Failed to get new fd");
-                                                    return;
-                                                };
-                                                if let Err(err) = pman
-                                                    .update_process_env(
-                                                        &key,
-                                                        vec![(
-                                                            "COSMIC_NOTIFICATIONS".to_string(),
-                                                            fd.as_raw_fd().to_string(),
-                                                        )],
-                                                    )
-                                                    .await
-                                                {
-                                                    error!("Failed to update process env: {}", err);
-                                                    return;
-                                                }
-                                                fds.push(fd);
-                                                fds.push(client_socket.into());
-                                                if let Err(err) =
-                                                    pman.update_process_fds(&key, move || fds).await
-                                                {
-                                                    error!("Failed to update process fds: {}", err);
-                                                    return;
-                                                }
-                                            } else {
-                                                fds.push(client_socket.into());
-                                                if let Err(err) =
-                                                    pman.update_process_fds(&key, move || fds).await
-                                                {
-                                                    error!("Failed to update process fds: {}", err);
-                                                    return;
-                                                }
-                                            }
-
-                                            if let Some(old_client) = my_list
-                                                .lock()
-                                                .unwrap()
-                                                .iter_mut()
-                                                .find(|(id, _, _, _)| id == &id_clone)
-                                            {
-                                                old_client.1 = c;
-                                                old_client.3 = security_context;
-                                                info!("Replaced the client socket");
-                                            } else {
-                                                error!(
-                                                    "Failed to find matching client... {}",
-                                                    &id_clone
-                                                )
-                                            }
-                                            let _ = applet_tx_clone
-                                                .send(AppletMsg::ClientSocketPair(client_id_clone))
-                                                .await;
-                                            applet_env.push((
-                                                "WAYLAND_SOCKET".to_string(),
-                                                raw_client_socket.to_string(),
-                                            ));
-                                            let _ = pman
-                                                .update_process_env(&key, applet_env.clone())
-                                                .await;
-                                        }
-                                    });
-
-                                let process_id = format!("{}-{}", self.id(), id);
-                                let msg = if is_notification_applet {
-                                    AppletMsg::NewNotificationsProcess(
-                                        process_id, process, applet_env, fds,
-                                    )
-                                } else {
-                                    process = process.with_fds(move || fds);
-
-                                    AppletMsg::NewProcess(process_id, process.with_env(applet_env))
-                                };
-                                match self.applet_tx.try_send(msg) {
-                                    Ok(_) => {}
-                                    Err(e) => error!("{e}"),
-                                };
-                            }
-                        }
-                    }
-                }
-            }
-
-            info!("Done spawning applets");
-            Ok(())
-        } else {
+        let spawned = [
+            &self.clients_left,
+            &self.clients_center,
+            &self.clients_right,
+        ]
+        .iter()
+        .any(|clients| !clients.lock().unwrap().is_empty());
+        if spawned {
             anyhow::bail!("Clients have already been spawned!");
         }
+        info!("Spawning applets");
+        self.update_clients(display, qh, security_context_manager)?;
+        info!("Done spawning applets");
+        Ok(())
     }
 
     fn destroy(&mut self) {
@@ -689,6 +363,20 @@ impl WrapperSpace for PanelSpace {
 
     /// returns false to forward the button press, and true to intercept
     fn handle_press(&mut self, seat_name: &str) -> Option<s_WlSurface> {
+        // pressing the indicator of a failed applet opens a popup with its error, and pressing it again closes it
+        if let Some((_, id)) = self
+            .hovered_failures
+            .iter()
+            .find(|(seat, _)| seat == seat_name)
+        {
+            if self.failure_popup.as_ref().map_or(false, |p| p.id == *id) {
+                self.failure_popup = None;
+            } else {
+                self.requested_failure = Some(id.clone());
+            }
+            return None;
+        }
+        self.failure_popup = None;
         if let Some(prev_foc) = {
             let c_hovered_surface: &ClientFocus = &self.c_hovered_surface.borrow();
 
@@ -726,6 +414,13 @@ impl WrapperSpace for PanelSpace {
         seat_name: &str,
         c_wl_surface: c_wl_surface::WlSurface,
     ) -> Option<ServerPointerFocus> {
+        self.hovered_failures.retain(|(seat, _)| seat != seat_name);
+        if self.layer.as_ref().map(|l| l.wl_surface()) == Some(&c_wl_surface) {
+            if let Some(id) = self.failed_applet_at((x as f64, y as f64).into()) {
+                self.hovered_failures
+                    .push((seat_name.to_string(), id.to_string()));
+            }
+        }
         let mut prev_hover = self
             .s_hovered_surface
             .iter_mut()
@@ -834,6 +529,7 @@ impl WrapperSpace for PanelSpace {
     fn pointer_leave(&mut self, seat_name: &str, _: Option<c_wl_surface::WlSurface>) {
         self.s_hovered_surface
             .retain(|focus| focus.seat_name != seat_name);
+        self.hovered_failures.retain(|(seat, _)| seat != seat_name);
     }
 
     fn pointer_enter(
@@ -911,14 +607,10 @@ impl WrapperSpace for PanelSpace {
         if let (Some(_c_output), Some(s_output), Some(output_info)) =
             (c_output.as_ref(), s_output.as_ref(), output_info.as_ref())
         {
-            self.space.map_output(s_output, output_info.location);
             match &self.config.output {
-                CosmicPanelOuput::Active => {
-                    bail!("output does not match config")
-                }
-                CosmicPanelOuput::Name(config_name)
-                    if output_info.name != Some(config_name.to_string()) =>
-                {
+                // the active output is chosen by the space container
+                CosmicPanelOuput::Active => {}
+                output if !output.matches(&output_match_info(output_info)) => {
                     bail!("output does not match config")
                 }
                 _ => {}
@@ -929,74 +621,16 @@ impl WrapperSpace for PanelSpace {
         } else if !matches!(self.config.output, CosmicPanelOuput::Active) {
             bail!("output does not match config");
         }
-        let dimensions: Size<i32, Logical> = self.constrain_dim((0, 0).into());
-
-        let layer = match self.config().layer() {
-            zwlr_layer_shell_v1::Layer::Background => Layer::Background,
-            zwlr_layer_shell_v1::Layer::Bottom => Layer::Bottom,
-            zwlr_layer_shell_v1::Layer::Top => Layer::Top,
-            zwlr_layer_shell_v1::Layer::Overlay => Layer::Overlay,
-            _ => bail!("Invalid layer"),
-        };
-
-        let surface = compositor_state.create_surface(&qh);
-        let client_surface =
-            layer_state.create_layer_surface(&qh, surface, layer, Some("Panel"), c_output.as_ref());
-        // client_surface.set_margin(margin.top, margin.right, margin.bottom, margin.left);
-        client_surface.set_keyboard_interactivity(match self.config.keyboard_interactivity {
-            xdg_shell_wrapper_config::KeyboardInteractivity::None => KeyboardInteractivity::None,
-            xdg_shell_wrapper_config::KeyboardInteractivity::Exclusive => {
-                KeyboardInteractivity::Exclusive
-            }
-            xdg_shell_wrapper_config::KeyboardInteractivity::OnDemand => {
-                KeyboardInteractivity::OnDemand
-            }
-        });
-        client_surface.set_size(
-            dimensions.w.try_into().unwrap(),
-            dimensions.h.try_into().unwrap(),
-        );
-
-        client_surface.set_anchor(match self.config.anchor {
-            cosmic_panel_config::PanelAnchor::Left => Anchor::all().difference(Anchor::RIGHT),
-            cosmic_panel_config::PanelAnchor::Right => Anchor::all().difference(Anchor::LEFT),
-            cosmic_panel_config::PanelAnchor::Top => Anchor::all().difference(Anchor::BOTTOM),
-            cosmic_panel_config::PanelAnchor::Bottom => Anchor::all().difference(Anchor::TOP),
-        });
-
-        if !self.config.expand_to_edges() {
-            let input_region = Region::new(compositor_state)?;
-            client_surface
-                .wl_surface()
-                .set_input_region(Some(input_region.wl_region()));
-            self.input_region.replace(input_region);
-        }
-
-        let fractional_scale = fractional_scale_manager
-            .map(|f| f.fractional_scaling(client_surface.wl_surface(), &qh));
-
-        let viewport = viewport.map(|v| v.get_viewport(client_surface.wl_surface(), &qh));
-
-        client_surface.commit();
-
-        let next_render_event = Rc::new(Cell::new(Some(SpaceEvent::WaitConfigure {
-            first: true,
-            width: dimensions.w,
-            height: dimensions.h,
-        })));
-
-        self.output = izip!(
-            c_output.into_iter(),
-            s_output.into_iter(),
-            output_info.as_ref().cloned()
-        )
-        .next();
-        self.layer = Some(client_surface);
-        self.layer_fractional_scale = fractional_scale;
-        self.layer_viewport = viewport;
-        self.dimensions = dimensions;
-        self.space_event = next_render_event;
-        self.is_dirty = true;
+        self.create_layer(
+            compositor_state,
+            fractional_scale_manager,
+            viewport,
+            layer_state,
+            qh,
+            c_output,
+            s_output,
+            output_info,
+        )?;
         if let Err(err) = self.spawn_clients(
             self.s_display.clone().unwrap(),
             &qh,
@@ -1112,3 +746,725 @@ impl WrapperSpace for PanelSpace {
         // TODO handle the preferred transform
     }
 }
+
+impl PanelSpace {
+    /// create the layer surface of the panel on the output, or on the output chosen by the compositor
+    pub(crate) fn create_layer<W: WrapperSpace>(
+        &mut self,
+        compositor_state: &sctk::compositor::CompositorState,
+        fractional_scale_manager: Option<&FractionalScalingManager<W>>,
+        viewport: Option<&ViewporterState<W>>,
+        layer_state: &mut LayerShell,
+        qh: &QueueHandle<GlobalState<W>>,
+        c_output: Option<c_wl_output::WlOutput>,
+        s_output: Option<Output>,
+        output_info: Option<OutputInfo>,
+    ) -> anyhow::Result<()> {
+        if let (Some(s_output), Some(output_info)) = (s_output.as_ref(), output_info.as_ref()) {
+            self.space.map_output(s_output, output_info.location);
+        }
+        let dimensions: Size<i32, Logical> = self.constrain_dim((0, 0).into());
+
+        let layer = match self.config().layer() {
+            zwlr_layer_shell_v1::Layer::Background => Layer::Background,
+            zwlr_layer_shell_v1::Layer::Bottom => Layer::Bottom,
+            zwlr_layer_shell_v1::Layer::Top => Layer::Top,
+            zwlr_layer_shell_v1::Layer::Overlay => Layer::Overlay,
+            _ => bail!("Invalid layer"),
+        };
+
+        let surface = compositor_state.create_surface(&qh);
+        let client_surface =
+            layer_state.create_layer_surface(&qh, surface, layer, Some("Panel"), c_output.as_ref());
+        // client_surface.set_margin(margin.top, margin.right, margin.bottom, margin.left);
+        client_surface.set_keyboard_interactivity(match self.config.keyboard_interactivity {
+            xdg_shell_wrapper_config::KeyboardInteractivity::None => KeyboardInteractivity::None,
+            xdg_shell_wrapper_config::KeyboardInteractivity::Exclusive => {
+                KeyboardInteractivity::Exclusive
+            }
+            xdg_shell_wrapper_config::KeyboardInteractivity::OnDemand => {
+                KeyboardInteractivity::OnDemand
+            }
+        });
+        client_surface.set_size(
+            dimensions.w.try_into().unwrap(),
+            dimensions.h.try_into().unwrap(),
+        );
+
+        client_surface.set_anchor(match self.config.anchor {
+            cosmic_panel_config::PanelAnchor::Left => Anchor::all().difference(Anchor::RIGHT),
+            cosmic_panel_config::PanelAnchor::Right => Anchor::all().difference(Anchor::LEFT),
+            cosmic_panel_config::PanelAnchor::Top => Anchor::all().difference(Anchor::BOTTOM),
+            cosmic_panel_config::PanelAnchor::Bottom => Anchor::all().difference(Anchor::TOP),
+        });
+
+        if self.config.is_dock() {
+            let input_region = Region::new(compositor_state)?;
+            client_surface
+                .wl_surface()
+                .set_input_region(Some(input_region.wl_region()));
+            self.input_region.replace(input_region);
+        }
+
+        let fractional_scale = fractional_scale_manager
+            .map(|f| f.fractional_scaling(client_surface.wl_surface(), &qh));
+
+        let viewport = viewport.map(|v| v.get_viewport(client_surface.wl_surface(), &qh));
+
+        client_surface.commit();
+
+        let next_render_event = Rc::new(Cell::new(Some(SpaceEvent::WaitConfigure {
+            first: true,
+            width: dimensions.w,
+            height: dimensions.h,
+        })));
+
+        self.output = izip!(
+            c_output.into_iter(),
+            s_output.into_iter(),
+            output_info.as_ref().cloned()
+        )
+        .next();
+        self.layer = Some(client_surface);
+        self.layer_fractional_scale = fractional_scale;
+        self.layer_viewport = viewport;
+        self.dimensions = dimensions;
+        self.space_event = next_render_event;
+        self.is_dirty = true;
+        Ok(())
+    }
+
+    /// move the layer surface of the panel to another output, keeping its applets running
+    pub(crate) fn move_to_output<W: WrapperSpace>(
+        &mut self,
+        compositor_state: &sctk::compositor::CompositorState,
+        fractional_scale_manager: Option<&FractionalScalingManager<W>>,
+        viewport: Option<&ViewporterState<W>>,
+        layer_state: &mut LayerShell,
+        qh: &QueueHandle<GlobalState<W>>,
+        c_output: c_wl_output::WlOutput,
+        s_output: Output,
+        output_info: OutputInfo,
+    ) -> anyhow::Result<()> {
+        info!(
+            "Moving {} to output {}",
+            self.config.name,
+            output_info.name.as_deref().unwrap_or("None")
+        );
+        self.replace_layer(
+            compositor_state,
+            fractional_scale_manager,
+            viewport,
+            layer_state,
+            qh,
+            Some(c_output),
+            Some(s_output),
+            Some(output_info),
+        )
+    }
+
+    /// apply a changed config to the running panel, keeping its applets running
+    /// the kind of the changes is found by diffing the configs, and changes which restart the applets
+    /// have to be applied by recreating the panel instead
+    pub(crate) fn reconfigure<W: WrapperSpace>(
+        &mut self,
+        compositor_state: &sctk::compositor::CompositorState,
+        fractional_scale_manager: Option<&FractionalScalingManager<W>>,
+        viewport: Option<&ViewporterState<W>>,
+        layer_state: &mut LayerShell,
+        qh: &QueueHandle<GlobalState<W>>,
+        config: CosmicPanelConfig,
+        bg_color: [f32; 4],
+        kind: ChangeKind,
+    ) -> anyhow::Result<()> {
+        if kind == ChangeKind::Restart {
+            bail!("{} has to be recreated to apply the changes", config.name);
+        }
+        info!("Reconfiguring {}", config.name);
+        self.apply_config(config, bg_color);
+
+        if kind == ChangeKind::Surface {
+            let (c_output, s_output, output_info) = match self.output.clone() {
+                Some((c_output, s_output, info)) => (Some(c_output), Some(s_output), Some(info)),
+                None => (None, None, None),
+            };
+            self.replace_layer(
+                compositor_state,
+                fractional_scale_manager,
+                viewport,
+                layer_state,
+                qh,
+                c_output,
+                s_output,
+                output_info,
+            )?;
+        } else {
+            self.clear();
+        }
+        // added, removed and changed applets are started and stopped individually
+        if let Some(s_display) = self.s_display.clone() {
+            self.update_clients(s_display, qh, self.security_context_manager.clone())?;
+        }
+        Ok(())
+    }
+
+    /// replace the config of the panel, resetting the state derived from it
+    /// the layer surface is left as it is
+    pub(crate) fn apply_config(&mut self, config: CosmicPanelConfig, mut bg_color: [f32; 4]) {
+        if config.autohide != self.config.autohide {
+            self.visibility = if config.autohide.is_none() {
+                Visibility::Visible
+            } else {
+                Visibility::Hidden
+            };
+        }
+        if config.is_horizontal() != self.config.is_horizontal() {
+            self.suggested_length = None;
+        }
+        *self.restart_policy.lock().unwrap() = config.restart_policy.clone();
+        *self.applet_logs.lock().unwrap() = config.applet_logs.clone();
+        bg_color[3] = config.background_alpha();
+        self.bg_color = bg_color;
+        self.bg_image = OnceCell::new();
+        // placeholders are tinted with the background
+        self.placeholders.clear();
+        // the next layout draws the buffer again, if the panel still needs one
+        self.old_buff = self.buffer.take();
+        self.buffer_changed = true;
+        self.config = config;
+    }
+
+    /// update the variables describing the panel, which started and restarted applets read
+    /// running applets keep the values they were started with, but log with the current output
+    pub(crate) fn update_applet_env(&self) -> Vec<(String, String)> {
+        let config_size = ron::ser::to_string(&self.config.size).unwrap_or_default();
+        let active_output = self
+            .output
+            .as_ref()
+            .and_then(|o| o.2.name.clone())
+            .unwrap_or_default();
+        // running applets log with the output the panel moved to
+        *self.output_name.lock().unwrap() = active_output.clone();
+
+        let config_anchor = ron::ser::to_string(&self.config.anchor).unwrap_or_default();
+        let config_bg = ron::ser::to_string(&self.config.background).unwrap_or_default();
+        let env_vars = vec![
+            ("COSMIC_PANEL_SIZE".to_string(), config_size),
+            ("COSMIC_PANEL_OUTPUT".to_string(), active_output),
+            ("COSMIC_PANEL_ANCHOR".to_string(), config_anchor),
+            ("COSMIC_PANEL_BACKGROUND".to_string(), config_bg),
+            ("RUST_BACKTRACE".to_string(), "1".to_string()),
+        ];
+        *self.applet_env.lock().unwrap() = env_vars.clone();
+        env_vars
+    }
+
+    /// start and stop applets so the client lists match the config
+    /// applets which are still configured with the same entry keep running, even if they moved to another list
+    pub(crate) fn update_clients<W: WrapperSpace>(
+        &mut self,
+        mut display: DisplayHandle,
+        qh: &QueueHandle<GlobalState<W>>,
+        security_context_manager: Option<SecurityContextManager>,
+    ) -> anyhow::Result<()> {
+        if self.process_id.is_none() {
+            self.process_id = Some(self.id());
+        }
+        let panel_id = self.process_id.clone().unwrap_or_default();
+        let env_vars = self.update_applet_env();
+        let lists = [
+            self.clients_left.clone(),
+            self.clients_center.clone(),
+            self.clients_right.clone(),
+        ];
+        let entries = [
+            self.config.plugins_left().unwrap_or_default(),
+            self.config.plugins_center().unwrap_or_default(),
+            self.config.plugins_right().unwrap_or_default(),
+        ];
+
+        let applet_registry = self.applet_registry.clone();
+        let registry = applet_registry.borrow();
+        let is_horizontal = self.config.is_horizontal();
+        let mut running: Vec<_> = lists
+            .iter()
+            .flat_map(|clients| mem::take(&mut *clients.lock().unwrap()))
+            .collect();
+        let mut desktop_ids = Vec::new();
+        for (clients, entries) in lists.iter().zip(entries.iter()) {
+            let mut clients = clients.lock().unwrap();
+            for entry in entries {
+                let instance_id = entry.instance_id();
+                // applets which don't support the orientation of the panel are stopped when it changes
+                let unchanged = self
+                    .applet_entries
+                    .iter()
+                    .find(|e| e.instance_id() == instance_id)
+                    == Some(entry)
+                    && registry.supports(entry.id(), is_horizontal);
+                match running
+                    .iter()
+                    .position(|(id, ..)| *id == instance_id)
+                    .filter(|_| unchanged)
+                {
+                    Some(i) => clients.push(running.remove(i)),
+                    None => {
+                        let (c, s) = get_client_sock(&mut display);
+                        clients.push((instance_id, c.clone(), None, None));
+                        desktop_ids.push((entry.clone(), c, s));
+                    }
+                }
+            }
+        }
+        self.applet_entries = entries.concat();
+        self.is_dirty = true;
+        // started applets get a new chance, and removed applets don't keep their space
+        for (entry, _, _) in &desktop_ids {
+            self.failed_applets.remove(&entry.instance_id());
+        }
+        self.applet_sizes
+            .retain(|id, _| self.applet_entries.iter().any(|e| e.instance_id() == *id));
+        self.failed_applets
+            .retain(|id, _| self.applet_entries.iter().any(|e| e.instance_id() == *id));
+
+        // the processes are stopped before the replacements of changed applets are started
+        for (id, client, _, _) in running {
+            info!("Stopping {}", id);
+            let windows: Vec<_> = self
+                .space
+                .elements()
+                .filter(|w| w.toplevel().wl_surface().client().map(|c| c.id()) == Some(client.id()))
+                .cloned()
+                .collect();
+            for w in windows {
+                self.space.unmap_elem(&w);
+            }
+            if let Err(err) = self
+                .applet_tx
+                .try_send(AppletMsg::Cleanup(format!("{}/{}", panel_id, id)))
+            {
+                error!("{err}");
+            }
+        }
+        if desktop_ids.is_empty() {
+            return Ok(());
+        }
+
+        info!(
+            "{:?}",
+            desktop_ids
+                .iter()
+                .map(|(e, ..)| e.instance_id())
+                .collect::<Vec<_>>()
+        );
+
+        for (applet_entry, client, socket) in desktop_ids {
+            // the applet is tracked by the id of its instance, and started from its desktop entry
+            let id = applet_entry.instance_id();
+            let Some(entry) = registry.get(applet_entry.id()).cloned() else {
+                warn!(
+                    "{} of {} has no desktop entry, it is started once one is installed",
+                    id, self.config.name
+                );
+                for clients in &lists {
+                    clients.lock().unwrap().retain(|(c_id, ..)| *c_id != id);
+                }
+                continue;
+            };
+            if !registry.supports(applet_entry.id(), is_horizontal) {
+                warn!(
+                    "{} doesn't support vertical panels, so it isn't started in {}",
+                    id, self.config.name
+                );
+                for clients in &lists {
+                    clients.lock().unwrap().retain(|(c_id, ..)| *c_id != id);
+                }
+                continue;
+            }
+            info!(
+                "Starting {} from {}: {}",
+                id,
+                entry.path.display(),
+                entry.launch
+            );
+
+            let requests_wayland_display = entry.requests_wayland_display;
+            let resource_limits = applet_entry
+                .resource_limits()
+                .map(|limits| limits.or(&self.config.resource_limits))
+                .unwrap_or_else(|| self.config.resource_limits.clone());
+            let process_id = format!("{}/{}", panel_id, id);
+            let mut fds = Vec::with_capacity(2);
+            let mut applet_env = Vec::new();
+
+            if requests_wayland_display {
+                if let Some(security_context_manager) = security_context_manager.as_ref() {
+                    match security_context_manager.create_listener::<W>(qh) {
+                        Ok(security_context) => {
+                            security_context.set_sandbox_engine(NAME.to_string());
+                            security_context.commit();
+
+                            let data = security_context.data::<SecurityContext>().unwrap();
+                            let privileged_socket = data.conn.lock().unwrap().take().unwrap();
+                            applet_env.push((
+                                "X_PRIVILEGED_WAYLAND_SOCKET".to_string(),
+                                privileged_socket.as_raw_fd().to_string(),
+                            ));
+                            fds.push(privileged_socket.into());
+                            with_client(&lists, &id, |applet| {
+                                applet.3 = Some(security_context);
+                            });
+                        }
+                        Err(why) => {
+                            error!(?why, "Failed to create a listener");
+                        }
+                    }
+                };
+            }
+
+            applet_env.push(("COSMIC_PANEL_APPLET_INSTANCE".to_string(), id.clone()));
+            applet_env.extend(env_for_applet(&env_vars, &applet_entry));
+            applet_env.push(("WAYLAND_SOCKET".to_string(), socket.as_raw_fd().to_string()));
+
+            fds.push(socket.into());
+            let fd_requests = entry.fds;
+
+            let command = match entry.launch {
+                AppletLaunch::Exec(command) => command,
+                AppletLaunch::DBus => {
+                    if !resource_limits.is_empty() {
+                        warn!(
+                            "{} is activated through D-Bus, so its resource limits aren't applied",
+                            id
+                        );
+                    }
+                    let msg = AppletMsg::Activate(
+                        process_id,
+                        applet_entry.id().to_string(),
+                        applet_entry.args().to_vec(),
+                        applet_env,
+                        fds,
+                        fd_requests,
+                    );
+                    if let Err(e) = self.applet_tx.try_send(msg) {
+                        error!("{e}");
+                    }
+                    continue;
+                }
+            };
+            let mut command = command.into_iter();
+            let exec = command.next().expect("Exec must name an executable");
+            let mut args = Vec::new();
+            for arg in command.chain(applet_entry.args().iter().cloned()) {
+                trace!("child argument: {}", &arg);
+                args.push(arg);
+            }
+            let (exec, args) = limited_command(exec, args, &resource_limits);
+            trace!("child: {}, {:?} {:?}", &exec, args, applet_env);
+
+            let display_handle = display.clone();
+            let applet_tx_clone = self.applet_tx.clone();
+            let id_clone = id.clone();
+            let client_id = client.id();
+            let log = Arc::new(Mutex::new(AppletLog::new(
+                id.clone(),
+                self.config.name.clone(),
+                self.output_name.clone(),
+                self.applet_logs.clone(),
+            )));
+            let log_stdout = log.clone();
+            let security_context_manager_clone = security_context_manager.clone();
+            let qh_clone = qh.clone();
+            let my_lists = lists.clone();
+            let panel_env = self.applet_env.clone();
+            let applet_entry_clone = applet_entry.clone();
+            let process_id_failed = process_id.clone();
+            let restart_policy = applet_entry.restart_policy().cloned();
+            let panel_restart_policy = self.restart_policy.clone();
+            let exits: Arc<Mutex<Vec<Instant>>> = Default::default();
+            let fd_requests_clone = fd_requests.clone();
+
+            let process = Process::new()
+                .with_executable(&exec)
+                .with_args(args)
+                .with_on_stderr(move |_, _, out| {
+                    let log = log.clone();
+                    async move {
+                        log.lock().unwrap().log(Stream::Stderr, &out);
+                    }
+                })
+                .with_on_stdout(move |_, _, out| {
+                    let log = log_stdout.clone();
+                    async move {
+                        log.lock().unwrap().log(Stream::Stdout, &out);
+                    }
+                })
+                .with_on_exit(move |mut pman, key, err_code, is_restarting| {
+                    let resource_limits = resource_limits.clone();
+                    let fd_requests = fd_requests_clone.clone();
+                    let my_lists = my_lists.clone();
+                    let process_id = process_id_failed.clone();
+                    let restart_policy =
+                        restart_policy.clone().unwrap_or_else(|| {
+                            panel_restart_policy.lock().unwrap().clone()
+                        });
+                    let exits = exits.clone();
+                    let mut display_handle = display_handle.clone();
+                    let id_clone = id_clone.clone();
+                    let applet_tx_clone = applet_tx_clone.clone();
+                    let (c, client_socket) = get_client_sock(&mut display_handle);
+                    let raw_client_socket = client_socket.as_raw_fd();
+                    let client_id_clone = client_id.clone();
+                    // the applet restarts with the panel as it is now
+                    let mut applet_env =
+                        env_for_applet(&panel_env.lock().unwrap(), &applet_entry_clone);
+                    let mut fds: Vec<OwnedFd> = Vec::with_capacity(2);
+                    let security_context = if requests_wayland_display {
+                        security_context_manager_clone.as_ref().and_then(
+                            |security_context_manager| {
+                                security_context_manager
+                                    .create_listener(&qh_clone)
+                                    .ok()
+                                    .map(|security_context| {
+                                        security_context
+                                            .set_sandbox_engine(NAME.to_string());
+                                        security_context.commit();
+
+                                        let data = security_context
+                                            .data::<SecurityContext>()
+                                            .unwrap();
+                                        let privileged_socket = data
+                                            .conn
+                                            .lock()
+                                            .unwrap()
+                                            .take()
+                                            .unwrap();
+                                        applet_env.push((
+                                            "X_PRIVILEGED_WAYLAND_SOCKET"
+                                                .to_string(),
+                                            privileged_socket
+                                                .as_raw_fd()
+                                                .to_string(),
+                                        ));
+                                        fds.push(privileged_socket.into());
+                                        security_context
+                                    })
+                            },
+                        )
+                    } else {
+                        None
+                    };
+
+                    async move {
+                        match err_code {
+                            Some(err_code) if resource_limits.is_empty() => error!(
+                                "{} exited with error code {}",
+                                id_clone, err_code
+                            ),
+                            Some(err_code) => error!(
+                                "{} exited with error code {}, it may have hit one of its resource limits: {}",
+                                id_clone, err_code, resource_limits
+                            ),
+                            // applets which hit their cpu or memory limit are usually killed by a signal,
+                            // which leaves no exit code
+                            None if is_restarting && !resource_limits.is_empty() => error!(
+                                "{} exited without a status, it may have been killed by a signal after hitting one of its resource limits: {}",
+                                id_clone, resource_limits
+                            ),
+                            None => {}
+                        }
+                        if !is_restarting {
+                            return;
+                        }
+
+                        // exits within the cooldown window count towards the restart limit
+                        let restarts = {
+                            let mut exits = exits.lock().unwrap();
+                            let now = Instant::now();
+                            exits.retain(|exit| {
+                                now.duration_since(*exit)
+                                    < restart_policy.cooldown()
+                            });
+                            exits.push(now);
+                            exits.len() as u32
+                        };
+                        if restarts > restart_policy.max_restarts {
+                            let reason = format!(
+                                "{} exited {} times within {}ms",
+                                id_clone, restarts, restart_policy.cooldown
+                            );
+                            error!("{}, not restarting it anymore", reason);
+                            let _ = pman.stop_process(key).await;
+                            let _ = applet_tx_clone
+                                .send(AppletMsg::Failed(process_id, reason))
+                                .await;
+                            return;
+                        }
+                        tokio::time::sleep(
+                            restart_policy.backoff.delay(restarts - 1),
+                        )
+                        .await;
+
+                        // the brokers hand out new fds for each start of the applet
+                        if !fd_requests.is_empty() {
+                            let (tx, rx) = oneshot::channel();
+                            _ = applet_tx_clone
+                                .send(AppletMsg::NeedFds(fd_requests, tx))
+                                .await;
+                            let res = match rx.await {
+                                Ok(Ok(broker_fds)) => Ok(broker_fds),
+                                Ok(Err(err)) => Err(format!("{:#}", err)),
+                                Err(err) => Err(err.to_string()),
+                            };
+                            // the applet can't start without its fds, so it is stopped and shown as failed
+                            let broker_fds = match res {
+                                Ok(broker_fds) => broker_fds,
+                                Err(err) => {
+                                    let reason = format!(
+                                        "{} couldn't be restarted: {}",
+                                        id_clone, err
+                                    );
+                                    error!("{}", reason);
+                                    let _ = pman.stop_process(key).await;
+                                    let _ = applet_tx_clone
+                                        .send(AppletMsg::Failed(process_id, reason))
+                                        .await;
+                                    return;
+                                }
+                            };
+                            let broker_env = broker_fds
+                                .iter()
+                                .map(|(var, fd)| (var.clone(), fd.as_raw_fd().to_string()))
+                                .collect();
+                            if let Err(err) = pman.update_process_env(&key, broker_env).await {
+                                error!("Failed to update process env: {}", err);
+                                return;
+                            }
+                            fds.extend(broker_fds.into_iter().map(|(_, fd)| fd));
+                        }
+                        fds.push(client_socket.into());
+                        if let Err(err) = pman.update_process_fds(&key, move || fds).await {
+                            error!("Failed to update process fds: {}", err);
+                            return;
+                        }
+
+                        // the applet may have moved to another list since it was started
+                        if with_client(&my_lists, &id_clone, |old_client| {
+                            old_client.1 = c;
+                            old_client.3 = security_context;
+                        })
+                        .is_some()
+                        {
+                            info!("Replaced the client socket");
+                        } else {
+                            error!(
+                                "Failed to find matching client... {}",
+                                &id_clone
+                            )
+                        }
+                        let _ = applet_tx_clone
+                            .send(AppletMsg::ClientSocketPair(client_id_clone))
+                            .await;
+                        applet_env.push((
+                            "WAYLAND_SOCKET".to_string(),
+                            raw_client_socket.to_string(),
+                        ));
+                        let _ =
+                            pman.update_process_env(&key, applet_env.clone()).await;
+                    }
+                });
+
+            let msg = AppletMsg::NewProcess(process_id, process, applet_env, fds, fd_requests);
+            match self.applet_tx.try_send(msg) {
+                Ok(_) => {}
+                Err(e) => error!("{e}"),
+            };
+        }
+
+        Ok(())
+    }
+
+    /// destroy the layer surface of the panel, along with its popups
+    pub(crate) fn destroy_layer(&mut self) {
+        // the egl surface has to be dropped before the layer surface
+        self.popups.clear();
+        self.failure_popup = None;
+        self.egl_surface = None;
+        self.damage_tracked_renderer = None;
+        self.layer_fractional_scale = None;
+        self.layer_viewport = None;
+        self.input_region = None;
+        self.layer = None;
+        if let Some((_, old_output, _)) = self.output.take() {
+            self.space.unmap_output(&old_output);
+        }
+    }
+
+    /// destroy the layer surface of the panel, and create a new one on the output
+    fn replace_layer<W: WrapperSpace>(
+        &mut self,
+        compositor_state: &sctk::compositor::CompositorState,
+        fractional_scale_manager: Option<&FractionalScalingManager<W>>,
+        viewport: Option<&ViewporterState<W>>,
+        layer_state: &mut LayerShell,
+        qh: &QueueHandle<GlobalState<W>>,
+        c_output: Option<c_wl_output::WlOutput>,
+        s_output: Option<Output>,
+        output_info: Option<OutputInfo>,
+    ) -> anyhow::Result<()> {
+        self.destroy_layer();
+        self.create_layer(
+            compositor_state,
+            fractional_scale_manager,
+            viewport,
+            layer_state,
+            qh,
+            c_output,
+            s_output,
+            output_info,
+        )
+    }
+}
+
+/// the variables of the panel and of the applet, where the size of the applet overrides the size of the panel
+fn env_for_applet(
+    panel_env: &[(String, String)],
+    applet_entry: &AppletEntry,
+) -> Vec<(String, String)> {
+    panel_env
+        .iter()
+        .map(|(key, val)| match (applet_entry.size(), key.as_str()) {
+            (Some(size), "COSMIC_PANEL_SIZE") => {
+                (key.clone(), ron::ser::to_string(size).unwrap_or_default())
+            }
+            _ => (key.clone(), val.clone()),
+        })
+        .chain(applet_entry.env().iter().cloned())
+        .collect()
+}
+
+/// modify the client of the applet, in whichever list it is
+fn with_client<R>(
+    lists: &[Clients],
+    id: &str,
+    f: impl FnOnce(
+        &mut (
+            String,
+            Client,
+            Option<UnixStream>,
+            Option<WpSecurityContextV1>,
+        ),
+    ) -> R,
+) -> Option<R> {
+    for clients in lists {
+        if let Some(client) = clients
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .find(|(c_id, ..)| c_id == id)
+        {
+            return Some(f(client));
+        }
+    }
+    None
+}
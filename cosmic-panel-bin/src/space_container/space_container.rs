@@ -1,7 +1,8 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{cell::RefCell, collections::HashMap, rc::Rc, time::Duration};
 
 use crate::{
-    space::{AppletMsg, PanelSpace},
+    applet_registry::AppletRegistry,
+    space::{output_match_info, AppletMsg, PanelSpace},
     PanelCalloopMsg,
 };
 use cctk::{
@@ -10,7 +11,8 @@ use cctk::{
 };
 use cosmic_config::CosmicConfigEntry;
 use cosmic_panel_config::{
-    CosmicPanelBackground, CosmicPanelConfig, CosmicPanelContainerConfig, CosmicPanelOuput,
+    ChangeKind, CosmicPanelBackground, CosmicPanelConfig, CosmicPanelContainerConfig,
+    CosmicPanelOuput, Validation,
 };
 use cosmic_theme::{palette, Theme, ThemeMode};
 use notify::RecommendedWatcher;
@@ -28,7 +30,7 @@ use smithay::{
     reexports::wayland_server::{self, backend::ClientId},
 };
 use tokio::sync::mpsc;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use wayland_server::Resource;
 use xdg_shell_wrapper::{
     client_state::ClientFocus, shared_state::GlobalState, space::WrapperSpace,
@@ -47,7 +49,10 @@ pub struct SpaceContainer {
     pub applet_tx: mpsc::Sender<AppletMsg>,
     pub panel_tx: calloop::channel::SyncSender<PanelCalloopMsg>,
     pub(crate) outputs: Vec<(WlOutput, Output, OutputInfo)>,
+    // output with keyboard or pointer focus, which panels configured for the active output are shown on
+    pub(crate) active_output: Option<WlOutput>,
     pub(crate) watchers: HashMap<String, RecommendedWatcher>,
+    pub(crate) applet_registry: Rc<RefCell<AppletRegistry>>,
     pub(crate) maximized_toplevels: Vec<(ZcosmicToplevelHandleV1, ToplevelInfo)>,
     pub(crate) toplevels: Vec<(ZcosmicToplevelHandleV1, ToplevelInfo)>,
     pub(crate) workspace_groups: Vec<WorkspaceGroup>,
@@ -59,10 +64,15 @@ pub struct SpaceContainer {
 
 impl SpaceContainer {
     pub fn new(
-        config: CosmicPanelContainerConfig,
+        mut config: CosmicPanelContainerConfig,
         tx: mpsc::Sender<AppletMsg>,
         panel_tx: calloop::channel::SyncSender<PanelCalloopMsg>,
     ) -> Self {
+        for profile in &mut config.config_list {
+            name_instances(profile);
+        }
+        log_validation(&config.sanitize());
+
         let is_dark = ThemeMode::config()
             .ok()
             .and_then(|c| ThemeMode::get_entry(&c).ok())
@@ -79,6 +89,7 @@ impl SpaceContainer {
             .unwrap_or_else(|| Theme::dark_default());
         let light = light.background.base;
         let dark = dark.background.base;
+        let applet_registry = Rc::new(RefCell::new(AppletRegistry::new(panel_tx.clone())));
 
         Self {
             connection: None,
@@ -91,7 +102,9 @@ impl SpaceContainer {
             applet_tx: tx,
             panel_tx,
             outputs: vec![],
+            active_output: None,
             watchers: HashMap::new(),
+            applet_registry,
             maximized_toplevels: Vec::with_capacity(1),
             toplevels: Vec::new(),
             workspace_groups: Vec::new(),
@@ -142,7 +155,8 @@ impl SpaceContainer {
                 continue;
             }
             space.config.opacity = opacity;
-            space.bg_color[3] = opacity;
+            space.config.clamp();
+            space.bg_color[3] = space.config.background_alpha();
             space.clear();
         }
     }
@@ -199,10 +213,106 @@ impl SpaceContainer {
         self.watchers.remove(&name);
     }
 
+    /// destroy the layer surfaces of the panels and stop their applets, before the panel exits
+    /// returns whether the applets stopped within the timeout
+    pub fn shutdown(&mut self, timeout: Duration) -> bool {
+        for s in &mut self.space_list {
+            s.destroy_layer();
+        }
+        // dropping the spaces sends `Cleanup` for their applets
+        self.space_list.clear();
+        if let Some(connection) = self.connection.as_ref() {
+            if let Err(err) = connection.flush() {
+                error!("Failed to destroy the layer surfaces: {}", err);
+            }
+        }
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        if let Err(err) = self.applet_tx.blocking_send(AppletMsg::Shutdown(tx)) {
+            error!("Failed to stop the applets: {}", err);
+            return false;
+        }
+        rx.recv_timeout(timeout).is_ok()
+    }
+
+    /// mark the applet of the process as failed, which shows a failed indicator in its place
+    pub fn applet_failed(&mut self, process_id: &str, error: String) {
+        for s in &mut self.space_list {
+            let Some(id) = s
+                .process_id
+                .as_ref()
+                .and_then(|panel_id| process_id.strip_prefix(panel_id.as_str()))
+                .and_then(|id| id.strip_prefix('/'))
+            else {
+                continue;
+            };
+            s.failed_applets.insert(id.to_string(), error);
+            s.is_dirty = true;
+            return;
+        }
+    }
+
+    /// open the popups of the failed applets whose indicators were pressed
+    pub fn show_failures<W: WrapperSpace>(
+        &mut self,
+        compositor_state: &sctk::compositor::CompositorState,
+        xdg_shell_state: &sctk::shell::xdg::XdgShell,
+        shm_state: &sctk::shm::Shm,
+        qh: &QueueHandle<GlobalState<W>>,
+    ) {
+        for space in &mut self.space_list {
+            if let Err(err) = space.show_failure(compositor_state, xdg_shell_state, shm_state, qh) {
+                error!("Failed to open the popup of a failed applet: {}", err);
+            }
+        }
+    }
+
+    /// read the desktop entries of the applets again, and start the configured applets which were installed
+    pub fn applets_changed<W: WrapperSpace>(&mut self, qh: &QueueHandle<GlobalState<W>>) {
+        self.applet_registry.borrow_mut().refresh();
+        for space in &mut self.space_list {
+            let running: Vec<_> = [
+                &space.clients_left,
+                &space.clients_center,
+                &space.clients_right,
+            ]
+            .iter()
+            .flat_map(|clients| {
+                clients
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|(id, ..)| id.clone())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+            let is_horizontal = space.config.is_horizontal();
+            let installed = space.applet_entries.iter().any(|e| {
+                let registry = self.applet_registry.borrow();
+                !running.iter().any(|id| *id == e.instance_id())
+                    && registry.contains(e.id())
+                    && registry.supports(e.id(), is_horizontal)
+            });
+            if !installed {
+                continue;
+            }
+            if let Some(s_display) = space.s_display.clone() {
+                let security_context_manager = space.security_context_manager.clone();
+                if let Err(err) = space.update_clients(s_display, qh, security_context_manager) {
+                    error!(
+                        "Failed to start the installed applets of {}: {}",
+                        space.config.name, err
+                    );
+                }
+            }
+        }
+    }
+
     /// apply a new or updated entry to the space list
+    /// panels which stay on their output are reconfigured in place if the changes allow it, keeping their applets running
     pub fn update_space<W: WrapperSpace>(
         &mut self,
-        entry: CosmicPanelConfig,
+        mut entry: CosmicPanelConfig,
         compositor_state: &sctk::compositor::CompositorState,
         fractional_scale_manager: Option<&FractionalScalingManager<W>>,
         viewport: Option<&ViewporterState<W>>,
@@ -210,13 +320,25 @@ impl SpaceContainer {
         qh: &QueueHandle<GlobalState<W>>,
         force_output: Option<WlOutput>,
     ) {
+        name_instances(&mut entry);
+        let validation = entry.validate();
+        log_validation(&validation);
+        if !validation.is_valid() {
+            error!("Keeping the previous config of {}", entry.name);
+            return;
+        }
+        entry.clamp();
+
         // exit early if the config hasn't actually changed
-        if !force_output.is_some() && self.space_list.iter_mut().any(|s| s.config == entry) {
+        // the spaces may have overrides of their output merged over the profile, so compare with the profile
+        if force_output.is_none()
+            && self.config.config_list.contains(&entry)
+            && self.space_list.iter().any(|s| s.config.name == entry.name)
+        {
             info!("config unchanged, skipping");
             return;
         }
 
-        // TODO: Lower priority panel surfaces are recreated on the same output as well after updating the config
         if let Some(config) = self
             .config
             .config_list
@@ -228,118 +350,365 @@ impl SpaceContainer {
             self.config.config_list.push(entry.clone());
         }
 
-        let connection = match self.connection.as_ref() {
-            Some(c) => c,
-            None => return,
-        };
+        if self.connection.is_none() {
+            return;
+        }
+
+        if let CosmicPanelOuput::Active = entry.output {
+            let active = self
+                .active_output
+                .as_ref()
+                .and_then(|active| self.outputs.iter().find(|(o, _, _)| o == active))
+                .cloned();
+            if let Some(i) = self
+                .space_list
+                .iter()
+                .position(|s| s.config.name == entry.name)
+            {
+                // the panel is moved to the active output separately, so it keeps its current output here
+                let output = self.space_list[i].output.as_ref().or(active.as_ref());
+                let config = match output {
+                    Some((_, _, info)) => entry.for_output(&output_match_info(info)),
+                    None => entry.clone(),
+                };
+                match self.space_list[i].config.diff(&config).kind() {
+                    None => return,
+                    Some(ChangeKind::Restart) => {}
+                    Some(kind) => {
+                        let bg_color = self.bg_color(&config.background);
+                        match self.space_list[i].reconfigure(
+                            compositor_state,
+                            fractional_scale_manager,
+                            viewport,
+                            layer_state,
+                            qh,
+                            config,
+                            bg_color,
+                            kind,
+                        ) {
+                            Ok(()) => {
+                                self.apply_toplevel_changes();
+                                return;
+                            }
+                            Err(err) => error!("Failed to reconfigure {}: {}", entry.name, err),
+                        }
+                    }
+                }
+                self.space_list.retain(|s| s.config.name != entry.name);
+            }
+            let (c_output, s_output, info) = match active {
+                Some((c_output, s_output, info)) => (Some(c_output), Some(s_output), Some(info)),
+                None => (None, None, None),
+            };
+            let config = match info.as_ref() {
+                Some(info) => entry.for_output(&output_match_info(info)),
+                None => entry.clone(),
+            };
+            if let Err(err) = self.create_space(
+                config,
+                compositor_state,
+                fractional_scale_manager,
+                viewport,
+                layer_state,
+                qh,
+                c_output,
+                s_output,
+                info,
+            ) {
+                error!("Failed to create space for active output: {}", err);
+            }
+            self.apply_toplevel_changes();
+            return;
+        }
 
-        // remove old one if it exists
+        let outputs: Vec<_> = self
+            .outputs
+            .iter()
+            .filter(|(wl_output, _, _)| {
+                force_output.is_none() || force_output.as_ref() == Some(wl_output)
+            })
+            .cloned()
+            .collect();
+        // remove the panel from the outputs it isn't shown on anymore
         self.space_list.retain(|s| {
             s.config.name != entry.name
-                || s.output
-                    .as_ref()
-                    .map(|(wl_output, _, _)| Some(wl_output) != force_output.as_ref())
-                    .unwrap_or_default()
+                || s.output.as_ref().map_or(false, |(_, _, info)| {
+                    entry.output.matches(&output_match_info(info))
+                })
         });
 
-        let outputs: Vec<_> = match &entry.output {
-            CosmicPanelOuput::Active => {
-                let mut space = PanelSpace::new(
-                    entry.clone(),
-                    self.c_focused_surface.clone(),
-                    self.c_hovered_surface.clone(),
-                    self.applet_tx.clone(),
-                    match entry.background {
-                        CosmicPanelBackground::ThemeDefault => self.cur_bg_color(),
-                        CosmicPanelBackground::Dark => self.dark_bg,
-                        CosmicPanelBackground::Light => self.light_bg,
-                        CosmicPanelBackground::Color(c) => [c[0], c[1], c[1], 1.0],
-                    },
-                    self.s_display.clone().unwrap(),
-                    self.security_context_manager.clone(),
-                    self.connection.as_ref().unwrap(),
-                );
-                if let Err(err) = space.new_output(
-                    compositor_state,
-                    fractional_scale_manager,
-                    viewport,
-                    layer_state,
-                    connection,
-                    qh,
-                    None,
-                    None,
-                    None,
-                ) {
-                    error!("Failed to create space for active output: {}", err);
-                } else {
-                    self.space_list.push(space);
-                }
-                vec![]
-            }
-            CosmicPanelOuput::All => self.outputs.iter().collect(),
-            CosmicPanelOuput::Name(name) => self
-                .outputs
-                .iter()
-                .filter(|(_, output, _)| &output.name() == name)
-                .collect(),
-        };
-
         let maximized_outputs = self.maximized_outputs();
         for (wl_output, output, info) in outputs {
-            if force_output.as_ref() != Some(wl_output) && force_output.is_some() {
-                continue;
-            }
-
-            let output_name = output.name();
-            let maximized_output = maximized_outputs.contains(wl_output);
-            let mut configs = self.config.configs_for_output(&output_name);
-            configs.sort_by(|a, b| b.get_priority().cmp(&a.get_priority()));
+            let maximized_output = maximized_outputs.contains(&wl_output);
+            let configs: Vec<_> = self
+                .config
+                .configs_for_output(&output_match_info(&info))
+                .into_iter()
+                .cloned()
+                .collect();
+            // once a layer surface is recreated, the panels after it in the stacking order are recreated as well
+            let mut restack = false;
             for c in configs {
-                self.space_list.retain(|s| {
-                    s.config.name != c.name || Some(wl_output) != s.output.as_ref().map(|o| &o.0)
-                });
-                let mut new_config = c.clone();
-                if maximized_output {
-                    new_config.expand_to_edges = true;
+                let mut new_config = c.for_output(&output_match_info(&info));
+                if let Some(mode) = new_config.mode.maximized().filter(|_| maximized_output) {
+                    new_config.mode = mode;
                     new_config.margin = 0;
                     new_config.border_radius = 0;
                     new_config.opacity = 1.0;
                 }
-                let mut space = PanelSpace::new(
-                    new_config.clone(),
-                    self.c_focused_surface.clone(),
-                    self.c_hovered_surface.clone(),
-                    self.applet_tx.clone(),
-                    match entry.background {
-                        CosmicPanelBackground::ThemeDefault => self.cur_bg_color(),
-                        CosmicPanelBackground::Dark => self.dark_bg,
-                        CosmicPanelBackground::Light => self.light_bg,
-                        CosmicPanelBackground::Color(c) => [c[0], c[1], c[1], 1.0],
-                    },
-                    self.s_display.clone().unwrap(),
-                    self.security_context_manager.clone(),
-                    self.connection.as_ref().unwrap(),
-                );
-                if let Some(s_display) = self.s_display.as_ref() {
-                    space.set_display_handle(s_display.clone());
-                }
-                if let Err(err) = space.new_output(
-                    compositor_state,
-                    fractional_scale_manager,
-                    viewport,
-                    layer_state,
-                    connection,
-                    qh,
-                    Some(wl_output.clone()),
-                    Some(output.clone()),
-                    Some(info.clone()),
-                ) {
-                    error!("Failed to create space for output: {}", err);
+                let existing = self.space_list.iter().position(|s| {
+                    s.config.name == c.name && s.output.as_ref().map(|o| &o.0) == Some(&wl_output)
+                });
+                let kind = match existing {
+                    Some(i) => self.space_list[i].config.diff(&new_config).kind(),
+                    None => Some(ChangeKind::Restart),
+                };
+                let kind = if restack {
+                    kind.max(Some(ChangeKind::Surface))
                 } else {
-                    self.space_list.push(space);
+                    kind
+                };
+                match (kind, existing) {
+                    (None, _) => {}
+                    (Some(kind), Some(i)) if kind != ChangeKind::Restart => {
+                        restack |= kind == ChangeKind::Surface;
+                        let bg_color = self.bg_color(&new_config.background);
+                        if let Err(err) = self.space_list[i].reconfigure(
+                            compositor_state,
+                            fractional_scale_manager,
+                            viewport,
+                            layer_state,
+                            qh,
+                            new_config,
+                            bg_color,
+                            kind,
+                        ) {
+                            error!("Failed to reconfigure {}: {}", c.name, err);
+                        }
+                    }
+                    (Some(_), _) => {
+                        restack = true;
+                        if let Some(i) = existing {
+                            self.space_list.remove(i);
+                        }
+                        if let Err(err) = self.create_space(
+                            new_config,
+                            compositor_state,
+                            fractional_scale_manager,
+                            viewport,
+                            layer_state,
+                            qh,
+                            Some(wl_output.clone()),
+                            Some(output.clone()),
+                            Some(info.clone()),
+                        ) {
+                            error!("Failed to create space for output: {}", err);
+                        }
+                    }
                 }
             }
         }
         self.apply_toplevel_changes();
     }
+
+    /// create a space for the panel and add it to the space list
+    fn create_space<W: WrapperSpace>(
+        &mut self,
+        config: CosmicPanelConfig,
+        compositor_state: &sctk::compositor::CompositorState,
+        fractional_scale_manager: Option<&FractionalScalingManager<W>>,
+        viewport: Option<&ViewporterState<W>>,
+        layer_state: &mut LayerShell,
+        qh: &QueueHandle<GlobalState<W>>,
+        c_output: Option<WlOutput>,
+        s_output: Option<Output>,
+        info: Option<OutputInfo>,
+    ) -> anyhow::Result<()> {
+        let Some(connection) = self.connection.as_ref() else {
+            anyhow::bail!("Missing connection");
+        };
+        let mut space = PanelSpace::new(
+            config.clone(),
+            self.c_focused_surface.clone(),
+            self.c_hovered_surface.clone(),
+            self.applet_tx.clone(),
+            self.applet_registry.clone(),
+            self.bg_color(&config.background),
+            self.s_display.clone().unwrap(),
+            self.security_context_manager.clone(),
+            connection,
+        );
+        if let Some(s_display) = self.s_display.as_ref() {
+            space.set_display_handle(s_display.clone());
+        }
+        space.new_output(
+            compositor_state,
+            fractional_scale_manager,
+            viewport,
+            layer_state,
+            connection,
+            qh,
+            c_output,
+            s_output,
+            info,
+        )?;
+        self.space_list.push(space);
+        Ok(())
+    }
+
+    /// background color of a panel, following the theme for theme backgrounds
+    fn bg_color(&self, background: &CosmicPanelBackground) -> [f32; 4] {
+        match background {
+            CosmicPanelBackground::ThemeDefault => self.cur_bg_color(),
+            CosmicPanelBackground::Dark => self.dark_bg,
+            CosmicPanelBackground::Light => self.light_bg,
+            background => background.color().unwrap_or_else(|| self.cur_bg_color()),
+        }
+    }
+
+    /// track the output with keyboard or pointer focus
+    /// panels configured for the active output are moved to it
+    pub(crate) fn set_active_output(&mut self, output: &WlOutput) {
+        if self.active_output.as_ref() == Some(output) {
+            return;
+        }
+        self.active_output = Some(output.clone());
+        if self
+            .space_list
+            .iter()
+            .any(|s| matches!(s.config.output, CosmicPanelOuput::Active))
+        {
+            _ = self
+                .panel_tx
+                .send(PanelCalloopMsg::ActiveOutput(output.clone()));
+        }
+    }
+
+    /// move the panels configured for the active output to the output, without restarting their applets
+    pub fn move_active_spaces<W: WrapperSpace>(
+        &mut self,
+        output: WlOutput,
+        compositor_state: &sctk::compositor::CompositorState,
+        fractional_scale_manager: Option<&FractionalScalingManager<W>>,
+        viewport: Option<&ViewporterState<W>>,
+        layer_state: &mut LayerShell,
+        qh: &QueueHandle<GlobalState<W>>,
+    ) {
+        // the active output may have changed again since the move was requested
+        if self.active_output.as_ref() != Some(&output) {
+            return;
+        }
+        let Some((c_output, s_output, info)) =
+            self.outputs.iter().find(|(o, _, _)| *o == output).cloned()
+        else {
+            return;
+        };
+        let match_info = output_match_info(&info);
+        let moves: Vec<_> = self
+            .space_list
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| {
+                matches!(s.config.output, CosmicPanelOuput::Active)
+                    && s.output.as_ref().map(|o| &o.0) != Some(&c_output)
+            })
+            .filter_map(|(i, s)| {
+                let profile = self
+                    .config
+                    .config_list
+                    .iter()
+                    .find(|c| c.name == s.config.name)?;
+                let config = profile.for_output(&match_info);
+                let bg_color = self.bg_color(&config.background);
+                Some((i, profile.clone(), config, bg_color))
+            })
+            .collect();
+        // panels with changes for the new output which can't be applied in place are recreated
+        let mut recreate = Vec::new();
+        for (i, profile, config, bg_color) in moves {
+            let space = &mut self.space_list[i];
+            if space.config.diff(&config).kind() == Some(ChangeKind::Restart) {
+                recreate.push(profile);
+                continue;
+            }
+            let applets = |config: &CosmicPanelConfig| {
+                (
+                    config.plugins_left(),
+                    config.plugins_center(),
+                    config.plugins_right(),
+                )
+            };
+            let applets_changed = applets(&space.config) != applets(&config);
+            space.apply_config(config, bg_color);
+            if let Err(err) = space.move_to_output(
+                compositor_state,
+                fractional_scale_manager,
+                viewport,
+                layer_state,
+                qh,
+                c_output.clone(),
+                s_output.clone(),
+                info.clone(),
+            ) {
+                error!(
+                    "Failed to move {} to the active output: {}",
+                    space.config.name, err
+                );
+            }
+            // running applets keep running, and only applets hidden by the overrides of either output
+            // are started or stopped
+            if !applets_changed {
+                space.update_applet_env();
+            } else if let Some(s_display) = space.s_display.clone() {
+                let security_context_manager = space.security_context_manager.clone();
+                if let Err(err) = space.update_clients(s_display, qh, security_context_manager) {
+                    error!(
+                        "Failed to update the applets of {}: {}",
+                        space.config.name, err
+                    );
+                }
+            }
+        }
+        for profile in recreate {
+            self.space_list.retain(|s| s.config.name != profile.name);
+            self.update_space(
+                profile,
+                compositor_state,
+                fractional_scale_manager,
+                viewport,
+                layer_state,
+                qh,
+                None,
+            );
+        }
+        self.apply_toplevel_changes();
+    }
+}
+
+/// name the later entries of applets which are in the profile more than once, and write the names to the config
+/// so each entry keeps its instance, and with it its settings and logs, when the entries are reordered
+fn name_instances(profile: &mut CosmicPanelConfig) {
+    if !profile.name_instances() {
+        return;
+    }
+    info!(
+        "Naming the instances of the applets which are in {} more than once",
+        profile.name
+    );
+    if let Err(err) = profile.write_mode() {
+        error!(
+            "Failed to write the instance names of {}: {:?}",
+            profile.name, err
+        );
+    }
+}
+
+/// log the problems found while validating a config
+fn log_validation(validation: &Validation) {
+    for issue in &validation.errors {
+        error!("Invalid panel config {}", issue);
+    }
+    for issue in &validation.warnings {
+        warn!("Clamping panel config {}", issue);
+    }
 }
@@ -3,6 +3,7 @@ use cctk::{
     toplevel_info::ToplevelInfo,
     wayland_client::{protocol::wl_output::WlOutput, Connection},
 };
+use cosmic_panel_config::CosmicPanelOuput;
 use xdg_shell_wrapper::space::ToplevelInfoSpace;
 
 use super::SpaceContainer;
@@ -17,6 +18,7 @@ impl ToplevelInfoSpace for SpaceContainer {
     ) {
         self.toplevels.push((toplevel.clone(), info.clone()));
         self.apply_toplevel_changes();
+        self.apply_activated(info);
 
         let is_maximized = info
             .state
@@ -41,6 +43,7 @@ impl ToplevelInfoSpace for SpaceContainer {
             *info_1 = info.clone();
         }
         self.apply_toplevel_changes();
+        self.apply_activated(info);
 
         let is_maximized = info
             .state
@@ -99,8 +102,11 @@ impl SpaceContainer {
     }
 
     pub(crate) fn apply_maximized(&self, output: &WlOutput) {
+        // panels configured for the active output move between outputs, and are not restarted
         let Some(config_name) = self.space_list.iter().find_map(|s| {
-            if s.output.as_ref().iter().any(|(o, _, _)| o == output) {
+            if s.output.as_ref().iter().any(|(o, _, _)| o == output)
+                && !matches!(s.config.output, CosmicPanelOuput::Active)
+            {
                 Some(s.config.name.clone())
             } else {
                 None
@@ -117,6 +123,19 @@ impl SpaceContainer {
         ));
     }
 
+    /// the output of the activated toplevel has keyboard focus
+    fn apply_activated(&mut self, info: &ToplevelInfo) {
+        if !info
+            .state
+            .contains(&zcosmic_toplevel_handle_v1::State::Activated)
+        {
+            return;
+        }
+        if let Some(output) = info.output.iter().next().cloned() {
+            self.set_active_output(&output);
+        }
+    }
+
     pub(crate) fn apply_toplevel_changes(&mut self) {
         for output in &self.outputs {
             let has_toplevel = self.toplevels.iter().any(|(_, info)| {
@@ -29,7 +29,10 @@ use xdg_shell_wrapper::{
     wp_viewporter::ViewporterState,
 };
 
-use crate::space::PanelSpace;
+use crate::{
+    space::{output_match_info, PanelSpace},
+    PanelCalloopMsg,
+};
 
 use super::SpaceContainer;
 
@@ -78,11 +81,14 @@ impl WrapperSpace for SpaceContainer {
                             self.c_focused_surface.clone(),
                             self.c_hovered_surface.clone(),
                             self.applet_tx.clone(),
+                            self.applet_registry.clone(),
                             match config.background {
                                 CosmicPanelBackground::ThemeDefault => self.cur_bg_color(),
                                 CosmicPanelBackground::Dark => self.dark_bg,
                                 CosmicPanelBackground::Light => self.light_bg,
-                                CosmicPanelBackground::Color(c) => [c[0], c[1], c[1], 1.0],
+                                ref background => {
+                                    background.color().unwrap_or_else(|| self.cur_bg_color())
+                                }
                             },
                             self.s_display.clone().unwrap(),
                             self.security_context_manager.clone(),
@@ -147,10 +153,10 @@ impl WrapperSpace for SpaceContainer {
             None => return Ok(()), // already created and set up
         };
 
-        let output_name = match output_info.name.clone() {
-            Some(n) => n,
-            None => anyhow::bail!("Output missing name"),
-        };
+        if output_info.name.is_none() {
+            anyhow::bail!("Output missing name");
+        }
+        let match_info = output_match_info(&output_info);
         self.outputs
             .push((c_output.clone(), s_output.clone(), output_info.clone()));
 
@@ -161,111 +167,65 @@ impl WrapperSpace for SpaceContainer {
         // create the spaces that are configured to use this output, including spaces configured for All
         let mut new_spaces = self
             .config
-            .configs_for_output(&output_name)
+            .configs_for_output(&match_info)
             .into_iter()
-            .filter_map(|config| match &config.output {
-                CosmicPanelOuput::All => {
-                    let c = match config.background {
-                        CosmicPanelBackground::ThemeDefault => cur,
-                        CosmicPanelBackground::Dark => dark,
-                        CosmicPanelBackground::Light => light,
-                        CosmicPanelBackground::Color(c) => [c[0], c[1], c[1], 1.0],
-                    };
-                    let mut s = if let Some(s) = self.space_list.iter_mut().position(|s| {
-                        s.config.name == config.name
-                            && Some(&c_output) == s.output.as_ref().map(|o| &o.0)
-                    }) {
-                        self.space_list.remove(s)
-                    } else {
-                        let mut s = PanelSpace::new(
-                            config.clone(),
-                            self.c_focused_surface.clone(),
-                            self.c_hovered_surface.clone(),
-                            self.applet_tx.clone(),
-                            c,
-                            self.s_display.clone().unwrap(),
-                            self.security_context_manager.clone(),
-                            conn,
-                        );
-                        s.setup(
-                            compositor_state,
-                            fractional_scale_manager,
-                            self.security_context_manager.clone(),
-                            viewport,
-                            layer_state,
-                            conn,
-                            qh,
-                        );
-                        if let Some(s_display) = self.s_display.as_ref() {
-                            s.set_display_handle(s_display.clone());
-                        }
-                        s
-                    };
-
-                    if s.new_output(
-                        compositor_state,
-                        fractional_scale_manager,
-                        viewport,
-                        layer_state,
+            .filter_map(|config| {
+                let config = config.for_output(&match_info);
+                let c = match config.background {
+                    CosmicPanelBackground::ThemeDefault => cur,
+                    CosmicPanelBackground::Dark => dark,
+                    CosmicPanelBackground::Light => light,
+                    ref background => background.color().unwrap_or(cur),
+                };
+                let mut s = if let Some(s) = self.space_list.iter_mut().position(|s| {
+                    s.config.name == config.name
+                        && Some(&c_output) == s.output.as_ref().map(|o| &o.0)
+                }) {
+                    self.space_list.remove(s)
+                } else {
+                    let mut s = PanelSpace::new(
+                        config.clone(),
+                        self.c_focused_surface.clone(),
+                        self.c_hovered_surface.clone(),
+                        self.applet_tx.clone(),
+                        self.applet_registry.clone(),
+                        c,
+                        self.s_display.clone().unwrap(),
+                        self.security_context_manager.clone(),
                         conn,
-                        qh,
-                        Some(c_output.clone()),
-                        Some(s_output.clone()),
-                        Some(output_info.clone()),
-                    )
-                    .is_ok()
-                    {
-                        Some(s)
-                    } else {
-                        None
-                    }
-                }
-                CosmicPanelOuput::Name(name) if name == &output_name => {
-                    let mut s = if let Some(s) = self.space_list.iter_mut().position(|s| {
-                        s.config.name == config.name && config.output == s.config.output
-                    }) {
-                        self.space_list.remove(s)
-                    } else {
-                        let mut s = PanelSpace::new(
-                            config.clone(),
-                            self.c_focused_surface.clone(),
-                            self.c_hovered_surface.clone(),
-                            self.applet_tx.clone(),
-                            match config.background {
-                                CosmicPanelBackground::ThemeDefault => cur,
-                                CosmicPanelBackground::Dark => dark,
-                                CosmicPanelBackground::Light => light,
-                                CosmicPanelBackground::Color(c) => [c[0], c[1], c[1], 1.0],
-                            },
-                            self.s_display.clone().unwrap(),
-                            self.security_context_manager.clone(),
-                            conn,
-                        );
-
-                        if let Some(s_display) = self.s_display.as_ref() {
-                            s.set_display_handle(s_display.clone());
-                        }
-                        s
-                    };
-                    if s.new_output(
+                    );
+                    s.setup(
                         compositor_state,
                         fractional_scale_manager,
+                        self.security_context_manager.clone(),
                         viewport,
                         layer_state,
                         conn,
                         qh,
-                        Some(c_output.clone()),
-                        Some(s_output.clone()),
-                        Some(output_info.clone()),
-                    )
-                    .is_ok()
-                    {
-                        Some(s)
-                    } else {
-                        None
+                    );
+                    if let Some(s_display) = self.s_display.as_ref() {
+                        s.set_display_handle(s_display.clone());
                     }
+                    s
+                };
+
+                if s.new_output(
+                    compositor_state,
+                    fractional_scale_manager,
+                    viewport,
+                    layer_state,
+                    conn,
+                    qh,
+                    Some(c_output.clone()),
+                    Some(s_output.clone()),
+                    Some(output_info.clone()),
+                )
+                .is_ok()
+                {
+                    Some(s)
+                } else {
+                    None
                 }
-                _ => None,
             })
             .collect_vec();
         self.space_list.append(&mut new_spaces);
@@ -462,6 +422,15 @@ impl WrapperSpace for SpaceContainer {
         seat_name: &str,
         c_wl_surface: c_wl_surface::WlSurface,
     ) -> Option<ServerPointerFocus> {
+        // the output of the hovered panel has pointer focus
+        if let Some(output) = self.space_list.iter().find_map(|s| {
+            (s.layer.as_ref().map(|l| l.wl_surface()) == Some(&c_wl_surface))
+                .then(|| s.output.as_ref().map(|o| o.0.clone()))
+                .flatten()
+        }) {
+            self.set_active_output(&output);
+        }
+
         if let Some((popup_space_i, popup_space)) = self
             .space_list
             .iter_mut()
@@ -487,7 +456,7 @@ impl WrapperSpace for SpaceContainer {
     }
 
     fn handle_press(&mut self, seat_name: &str) -> Option<wl_surface::WlSurface> {
-        if let Some((popup_space_i, popup_space)) = self
+        let ret = if let Some((popup_space_i, popup_space)) = self
             .space_list
             .iter_mut()
             .enumerate()
@@ -508,7 +477,16 @@ impl WrapperSpace for SpaceContainer {
             self.space_list
                 .iter_mut()
                 .find_map(|s| s.handle_press(seat_name))
+        };
+        // popups need the shell globals, which are only reachable from the event loop
+        if self
+            .space_list
+            .iter()
+            .any(|s| s.requested_failure.is_some())
+        {
+            let _ = self.panel_tx.send(PanelCalloopMsg::ShowFailures);
         }
+        ret
     }
 
     fn keyboard_leave(&mut self, seat_name: &str, surface: Option<c_wl_surface::WlSurface>) {
@@ -585,6 +563,15 @@ impl WrapperSpace for SpaceContainer {
         seat_name: &str,
         c_wl_surface: c_wl_surface::WlSurface,
     ) -> Option<ServerPointerFocus> {
+        // the output of the hovered panel has pointer focus
+        if let Some(output) = self.space_list.iter().find_map(|s| {
+            (s.layer.as_ref().map(|l| l.wl_surface()) == Some(&c_wl_surface))
+                .then(|| s.output.as_ref().map(|o| o.0.clone()))
+                .flatten()
+        }) {
+            self.set_active_output(&output);
+        }
+
         if let Some((popup_space_i, popup_space)) = self
             .space_list
             .iter_mut()
@@ -614,6 +601,13 @@ impl WrapperSpace for SpaceContainer {
         popup: &sctk::shell::xdg::popup::Popup,
         config: sctk::shell::xdg::popup::PopupConfigure,
     ) {
+        if self
+            .space_list
+            .iter_mut()
+            .any(|s| s.configure_failure_popup(popup))
+        {
+            return;
+        }
         if let Some(space) = self.space_list.iter_mut().find(|s| {
             s.popups
                 .iter()
@@ -643,6 +637,13 @@ impl WrapperSpace for SpaceContainer {
     fn raise_window(&mut self, _: &smithay::desktop::Window, _: bool) {}
 
     fn close_popup(&mut self, popup: &sctk::shell::xdg::popup::Popup) {
+        if self
+            .space_list
+            .iter_mut()
+            .any(|s| s.close_failure_popup(popup))
+        {
+            return;
+        }
         if let Some(space) = self.space_list.iter_mut().find(|s| {
             s.popups
                 .iter()
@@ -663,8 +664,30 @@ impl WrapperSpace for SpaceContainer {
     }
 
     fn close_layer(&mut self, layer: &LayerSurface) {
-        self.space_list
-            .retain(|s| s.layer.as_ref().map(|s| s.wl_surface()) != Some(layer.wl_surface()));
+        let Some(i) = self
+            .space_list
+            .iter()
+            .position(|s| s.layer.as_ref().map(|s| s.wl_surface()) == Some(layer.wl_surface()))
+        else {
+            return;
+        };
+        // keep panels configured for the active output running if they can be moved to another output
+        let space = &self.space_list[i];
+        let other_output = self
+            .outputs
+            .iter()
+            .map(|o| &o.0)
+            .find(|o| space.output.as_ref().map(|s_o| &s_o.0) != Some(*o))
+            .cloned();
+        match other_output {
+            Some(output) if matches!(space.config.output, CosmicPanelOuput::Active) => {
+                self.active_output = None;
+                self.set_active_output(&output);
+            }
+            _ => {
+                self.space_list.remove(i);
+            }
+        }
     }
 
     fn output_leave(
@@ -673,8 +696,18 @@ impl WrapperSpace for SpaceContainer {
         _s_output: Output,
     ) -> anyhow::Result<()> {
         self.outputs.retain(|o| o.0 != c_output);
-        self.space_list
-            .retain(|s| s.output.as_ref().map(|o| &o.0) != Some(&c_output));
+        let fallback = self.outputs.first().map(|o| o.0.clone());
+        // panels configured for the active output are moved to another output if there is one
+        self.space_list.retain(|s| {
+            s.output.as_ref().map(|o| &o.0) != Some(&c_output)
+                || (fallback.is_some() && matches!(s.config.output, CosmicPanelOuput::Active))
+        });
+        if self.active_output.as_ref() == Some(&c_output) {
+            self.active_output = None;
+            if let Some(output) = fallback {
+                self.set_active_output(&output);
+            }
+        }
         Ok(())
     }
 
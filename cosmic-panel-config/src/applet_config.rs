@@ -0,0 +1,437 @@
+//! Config for the applets of a cosmic panel
+
+use std::{fmt::Display, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::PanelSize;
+
+/// An applet entry in one of the plugin lists of a panel.
+/// Either the bare desktop file id of the applet, or a structured record with per-applet settings.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum AppletEntry {
+    /// desktop file id of the applet
+    Id(String),
+    /// desktop file id of the applet with additional settings
+    Config(AppletConfig),
+}
+
+/// Per-applet settings
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct AppletConfig {
+    /// desktop file id of the applet
+    pub id: String,
+    /// name of the instance, which tells apart the entries of the same applet in a panel
+    #[serde(default)]
+    pub instance: Option<String>,
+    /// arguments appended to the Exec line of the applet
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// environment variables set for the applet
+    #[serde(default)]
+    pub env: Vec<(String, String)>,
+    /// size passed to the applet instead of the panel size
+    #[serde(default)]
+    pub size: Option<PanelSize>,
+    /// whether the applet should be started
+    #[serde(default = "enabled_default")]
+    pub enabled: bool,
+    /// restart policy of the applet instead of the restart policy of the panel
+    #[serde(default)]
+    pub restart_policy: Option<RestartPolicy>,
+    /// resource limits of the applet, each unset limit falls back to the limit of the panel
+    #[serde(default)]
+    pub resource_limits: ResourceLimits,
+    /// watchdog of the applet instead of the watchdog of the panel
+    #[serde(default)]
+    pub watchdog: Option<Watchdog>,
+}
+
+fn enabled_default() -> bool {
+    true
+}
+
+/// How an applet is restarted after it exits
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct RestartPolicy {
+    /// restarts within the cooldown window, after which the applet is marked as failed and not restarted anymore
+    #[serde(default = "max_restarts_default")]
+    pub max_restarts: u32,
+    /// delay before each restart
+    #[serde(default)]
+    pub backoff: Backoff,
+    /// time in milliseconds after which an exit of the applet is forgotten
+    #[serde(default = "cooldown_default")]
+    pub cooldown: u64,
+}
+
+fn max_restarts_default() -> u32 {
+    5
+}
+
+fn cooldown_default() -> u64 {
+    60_000
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            max_restarts: max_restarts_default(),
+            backoff: Backoff::default(),
+            cooldown: cooldown_default(),
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// get the time after which an exit of the applet is forgotten
+    pub fn cooldown(&self) -> Duration {
+        Duration::from_millis(self.cooldown)
+    }
+}
+
+/// Delay before an applet is restarted
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub enum Backoff {
+    /// restart immediately
+    None,
+    /// wait the same number of milliseconds before each restart
+    Fixed(u64),
+    /// wait `initial` milliseconds before the first restart within the cooldown window,
+    /// and double the delay with each further restart, up to `max` milliseconds
+    Exponential { initial: u64, max: u64 },
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff::Exponential {
+            initial: 100,
+            max: 10_000,
+        }
+    }
+}
+
+impl Backoff {
+    /// get the delay before the restart, given the number of earlier restarts within the cooldown window
+    pub fn delay(&self, restarts: u32) -> Duration {
+        match self {
+            Backoff::None => Duration::ZERO,
+            Backoff::Fixed(delay) => Duration::from_millis(*delay),
+            Backoff::Exponential { initial, max } => Duration::from_millis(
+                initial
+                    .saturating_mul(1u64.checked_shl(restarts).unwrap_or(u64::MAX))
+                    .min(*max),
+            ),
+        }
+    }
+}
+
+/// How the panel checks that an applet shows its window and keeps responding
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Watchdog {
+    /// time in milliseconds from the start of the applet to its first window, 0 disables the deadline
+    #[serde(default = "startup_timeout_default")]
+    pub startup_timeout: u64,
+    /// time in milliseconds between pings of the window of the applet, or `None` to not ping it
+    /// an applet which didn't answer a ping when the next one is due is hung
+    #[serde(default)]
+    pub ping_interval: Option<u64>,
+    /// what happens to an applet which missed its deadline or is hung
+    #[serde(default)]
+    pub action: WatchdogAction,
+}
+
+fn startup_timeout_default() -> u64 {
+    10_000
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self {
+            startup_timeout: startup_timeout_default(),
+            ping_interval: None,
+            action: WatchdogAction::default(),
+        }
+    }
+}
+
+impl Watchdog {
+    /// get the time from the start of the applet to its first window, if there is a deadline
+    pub fn startup_timeout(&self) -> Option<Duration> {
+        (self.startup_timeout > 0).then(|| Duration::from_millis(self.startup_timeout))
+    }
+
+    /// get the time between pings of the window of the applet, if it is pinged
+    pub fn ping_interval(&self) -> Option<Duration> {
+        self.ping_interval.map(Duration::from_millis)
+    }
+}
+
+/// What happens to an applet which doesn't show its window or stops responding
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum WatchdogAction {
+    /// stop the applet and start it again, until it hangs more often than its restart policy allows
+    #[default]
+    Restart,
+    /// stop the applet and show it as failed
+    Fail,
+}
+
+/// Resource limits applied to an applet process when it is spawned
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ResourceLimits {
+    /// maximum size of the address space in bytes
+    #[serde(default)]
+    pub address_space: Option<u64>,
+    /// maximum number of open file descriptors
+    #[serde(default)]
+    pub open_files: Option<u64>,
+    /// maximum CPU time in seconds
+    #[serde(default)]
+    pub cpu_time: Option<u64>,
+    /// nice level, from -20 to 19
+    #[serde(default)]
+    pub nice: Option<i32>,
+}
+
+impl ResourceLimits {
+    /// whether no limit is set
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// get the limits, with the limits of `fallback` in place of the unset ones
+    pub fn or(&self, fallback: &Self) -> Self {
+        Self {
+            address_space: self.address_space.or(fallback.address_space),
+            open_files: self.open_files.or(fallback.open_files),
+            cpu_time: self.cpu_time.or(fallback.cpu_time),
+            nice: self.nice.or(fallback.nice),
+        }
+    }
+}
+
+impl Display for ResourceLimits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut limits = Vec::new();
+        if let Some(bytes) = self.address_space {
+            limits.push(format!("address space {} bytes", bytes));
+        }
+        if let Some(files) = self.open_files {
+            limits.push(format!("{} open files", files));
+        }
+        if let Some(seconds) = self.cpu_time {
+            limits.push(format!("CPU time {}s", seconds));
+        }
+        if let Some(nice) = self.nice {
+            limits.push(format!("nice {}", nice));
+        }
+        write!(f, "{}", limits.join(", "))
+    }
+}
+
+/// How the output of the applets of a panel is logged
+/// each line is written to journald, with the applet id as its syslog identifier
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct AppletLogs {
+    /// also write the output of each applet to its own log file under `$XDG_STATE_HOME/cosmic-panel/applets`
+    #[serde(default)]
+    pub files: Option<LogFiles>,
+    /// lines an applet may log within the interval, further lines are dropped until the interval ends
+    /// 0 disables the rate limit
+    #[serde(default = "burst_default")]
+    pub burst: u32,
+    /// length of the rate limit interval in milliseconds
+    #[serde(default = "interval_default")]
+    pub interval: u64,
+}
+
+fn burst_default() -> u32 {
+    200
+}
+
+fn interval_default() -> u64 {
+    10_000
+}
+
+impl Default for AppletLogs {
+    fn default() -> Self {
+        Self {
+            files: None,
+            burst: burst_default(),
+            interval: interval_default(),
+        }
+    }
+}
+
+impl AppletLogs {
+    /// get the length of the rate limit interval
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval)
+    }
+}
+
+/// Rotation of the log files of applets
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct LogFiles {
+    /// size in bytes after which a log file is rotated
+    #[serde(default = "max_size_default")]
+    pub max_size: u64,
+    /// rotated log files which are kept besides the current one
+    #[serde(default = "max_files_default")]
+    pub max_files: u32,
+}
+
+fn max_size_default() -> u64 {
+    1024 * 1024
+}
+
+fn max_files_default() -> u32 {
+    3
+}
+
+impl Default for LogFiles {
+    fn default() -> Self {
+        Self {
+            max_size: max_size_default(),
+            max_files: max_files_default(),
+        }
+    }
+}
+
+impl AppletConfig {
+    /// create a config for the applet with default settings
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            instance: None,
+            args: Vec::new(),
+            env: Vec::new(),
+            size: None,
+            enabled: true,
+            restart_policy: None,
+            resource_limits: ResourceLimits::default(),
+            watchdog: None,
+        }
+    }
+}
+
+impl AppletEntry {
+    /// get the desktop file id of the applet
+    pub fn id(&self) -> &str {
+        match self {
+            AppletEntry::Id(id) => id,
+            AppletEntry::Config(c) => &c.id,
+        }
+    }
+
+    /// get the id of the instance of the applet, which is unique in its panel
+    /// the desktop file id, followed by `#` and the name of the instance if it has one
+    pub fn instance_id(&self) -> String {
+        match self {
+            AppletEntry::Config(AppletConfig {
+                id,
+                instance: Some(instance),
+                ..
+            }) => format!("{}#{}", id, instance),
+            entry => entry.id().to_string(),
+        }
+    }
+
+    /// get the entry with the name of the instance
+    pub fn with_instance(self, instance: String) -> Self {
+        let mut config = match self {
+            AppletEntry::Id(id) => AppletConfig::new(id),
+            AppletEntry::Config(config) => config,
+        };
+        config.instance = Some(instance);
+        AppletEntry::Config(config)
+    }
+
+    /// get the additional arguments of the applet
+    pub fn args(&self) -> &[String] {
+        match self {
+            AppletEntry::Id(_) => &[],
+            AppletEntry::Config(c) => &c.args,
+        }
+    }
+
+    /// get the additional environment variables of the applet
+    pub fn env(&self) -> &[(String, String)] {
+        match self {
+            AppletEntry::Id(_) => &[],
+            AppletEntry::Config(c) => &c.env,
+        }
+    }
+
+    /// get the size override of the applet
+    pub fn size(&self) -> Option<&PanelSize> {
+        match self {
+            AppletEntry::Id(_) => None,
+            AppletEntry::Config(c) => c.size.as_ref(),
+        }
+    }
+
+    /// get the restart policy override of the applet
+    pub fn restart_policy(&self) -> Option<&RestartPolicy> {
+        match self {
+            AppletEntry::Id(_) => None,
+            AppletEntry::Config(c) => c.restart_policy.as_ref(),
+        }
+    }
+
+    /// get the watchdog override of the applet
+    pub fn watchdog(&self) -> Option<&Watchdog> {
+        match self {
+            AppletEntry::Id(_) => None,
+            AppletEntry::Config(c) => c.watchdog.as_ref(),
+        }
+    }
+
+    /// get the resource limits of the applet, without the limits of the panel
+    pub fn resource_limits(&self) -> Option<&ResourceLimits> {
+        match self {
+            AppletEntry::Id(_) => None,
+            AppletEntry::Config(c) => Some(&c.resource_limits),
+        }
+    }
+
+    /// whether the applet should be started
+    pub fn is_enabled(&self) -> bool {
+        match self {
+            AppletEntry::Id(_) => true,
+            AppletEntry::Config(c) => c.enabled,
+        }
+    }
+}
+
+/// get the desktop file id of an instance id
+pub fn desktop_id(instance_id: &str) -> &str {
+    instance_id
+        .split_once('#')
+        .map_or(instance_id, |(id, _)| id)
+}
+
+impl From<String> for AppletEntry {
+    fn from(id: String) -> Self {
+        AppletEntry::Id(id)
+    }
+}
+
+impl From<&str> for AppletEntry {
+    fn from(id: &str) -> Self {
+        AppletEntry::Id(id.to_string())
+    }
+}
+
+impl From<AppletConfig> for AppletEntry {
+    fn from(config: AppletConfig) -> Self {
+        AppletEntry::Config(config)
+    }
+}
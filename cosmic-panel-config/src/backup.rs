@@ -0,0 +1,136 @@
+//! Backups of a panel config which failed to load
+//!
+//! Before the fallback config is written over a broken config, the current version of the container config
+//! and of each panel profile is copied to `$XDG_STATE_HOME/cosmic-panel/backups/<unix timestamp>/`.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use cosmic_config::{Config, ConfigGet};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    migration::{config_root, read_keys, version_dir},
+    CosmicPanelConfig, CosmicPanelContainerConfig, NAME, VERSION,
+};
+
+impl CosmicPanelConfig {
+    /// keys of the profile which are missing or fail to parse
+    pub fn invalid_keys(config: &Config) -> Vec<&'static str> {
+        fn is_valid<T: DeserializeOwned>(_: &T, config: &Config, key: &str) -> bool {
+            config.get::<T>(key).is_ok()
+        }
+
+        // the default config is only used to infer the type of each key
+        let default = Self::default();
+        let mut invalid = Vec::new();
+        macro_rules! check {
+            ($($key:ident),* $(,)?) => {
+                $(
+                    if !is_valid(&default.$key, config, stringify!($key)) {
+                        invalid.push(stringify!($key));
+                    }
+                )*
+            };
+        }
+        check!(
+            name,
+            anchor,
+            anchor_gap,
+            layer,
+            keyboard_interactivity,
+            size,
+            output,
+            background,
+            mode,
+            padding,
+            spacing,
+            border_radius,
+            exclusive_zone,
+            autohide,
+            margin,
+            opacity,
+            priority,
+            owns_corners,
+            overrides,
+            restart_policy,
+            resource_limits,
+            applet_logs,
+            watchdog,
+        );
+        invalid
+    }
+}
+
+impl CosmicPanelContainerConfig {
+    /// keys of the panel config which are missing or fail to parse, as `<config name>/<key>`
+    pub fn invalid_keys() -> Vec<String> {
+        let Ok(config) = Self::cosmic_config() else {
+            return vec![format!("{}/entries", NAME)];
+        };
+        let Ok(entries) = config.get::<Vec<String>>("entries") else {
+            return vec![format!("{}/entries", NAME)];
+        };
+        entries
+            .into_iter()
+            .flat_map(|entry| {
+                let name = format!("{}.{}", NAME, entry);
+                match Config::new(&name, VERSION) {
+                    Ok(config) => CosmicPanelConfig::invalid_keys(&config)
+                        .into_iter()
+                        .map(|key| format!("{}/{}", name, key))
+                        .collect(),
+                    Err(_) => vec![name],
+                }
+            })
+            .collect()
+    }
+
+    /// copy the user's panel config to a new timestamped backup directory
+    /// returns the backup directory, or None if the user has no panel config to back up
+    pub fn backup() -> io::Result<Option<PathBuf>> {
+        let Some(root) = config_root() else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "failed to find the config directory",
+            ));
+        };
+
+        let profile_prefix = format!("{}.", NAME);
+        let configs = fs::read_dir(&root)?
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .filter(|name| name == NAME || name.starts_with(&profile_prefix))
+            .filter(|name| !read_keys(&version_dir(&root, name, VERSION)).is_empty())
+            .collect::<Vec<_>>();
+        if configs.is_empty() {
+            return Ok(None);
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        let dirs = xdg::BaseDirectories::with_prefix("cosmic-panel")?;
+        // backups made within the same millisecond get a suffix instead of replacing each other
+        let mut name = timestamp.to_string();
+        let mut suffix = 1;
+        while dirs.get_state_home().join("backups").join(&name).exists() {
+            name = format!("{}-{}", timestamp, suffix);
+            suffix += 1;
+        }
+        let backup_dir = dirs.create_state_directory(Path::new("backups").join(name))?;
+
+        for name in configs {
+            let keys = read_keys(&version_dir(&root, &name, VERSION));
+            let dest = version_dir(&backup_dir, &name, VERSION);
+            fs::create_dir_all(&dest)?;
+            for (key, value) in keys {
+                fs::write(dest.join(key), value)?;
+            }
+        }
+        Ok(Some(backup_dir))
+    }
+}
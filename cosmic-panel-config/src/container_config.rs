@@ -1,12 +1,13 @@
-use crate::{CosmicPanelBackground, CosmicPanelConfig, CosmicPanelOuput};
+use crate::{
+    CosmicPanelBackground, CosmicPanelConfig, CosmicPanelOuput, OutputMatchInfo, Validation,
+};
 use cosmic_config::{Config, ConfigGet, ConfigSet, CosmicConfigEntry};
 use serde::{Deserialize, Serialize};
-use tracing::warn;
+use tracing::{info, warn};
 use xdg_shell_wrapper_config::{Layer, WrapperConfig, WrapperOutput};
 
 /// Config structure for the cosmic panel
 #[derive(Debug, Deserialize, Serialize, Clone)]
-#[serde(deny_unknown_fields)]
 pub struct CosmicPanelContainerConfig {
     pub config_list: Vec<CosmicPanelConfig>,
 }
@@ -34,11 +35,12 @@ impl WrapperConfig for CosmicPanelContainerConfig {
 }
 
 pub const NAME: &str = "com.system76.CosmicPanel";
-pub const VERSION: u64 = 1;
+pub const VERSION: u64 = 4;
 
 impl CosmicPanelContainerConfig {
-    /// load config with the provided name
+    /// load config with the provided name, migrating it from older versions if necessary
     pub fn load() -> Result<Self, (Vec<cosmic_config::Error>, Self)> {
+        crate::migration::migrate();
         let config = match Self::cosmic_config() {
             Ok(config) => config,
             Err(e) => {
@@ -81,17 +83,14 @@ impl CosmicPanelContainerConfig {
         }
     }
 
-    pub fn configs_for_output(&self, output_name: &str) -> Vec<&CosmicPanelConfig> {
+    /// get the configs of the panels which should be shown on the output, in the order they should be created
+    pub fn configs_for_output(&self, output: &OutputMatchInfo) -> Vec<&CosmicPanelConfig> {
         let mut configs: Vec<_> = self
             .config_list
             .iter()
-            .filter(|c| match &c.output {
-                CosmicPanelOuput::All => true,
-                CosmicPanelOuput::Name(n) => n == output_name,
-                _ => false,
-            })
+            .filter(|c| c.output.matches(output))
             .collect();
-        configs.sort_by(|a, b| b.get_priority().cmp(&a.get_priority()));
+        configs.sort_by(|a, b| a.cmp_stacking(b));
         configs
     }
 
@@ -113,6 +112,44 @@ impl CosmicPanelContainerConfig {
         }
         Ok(())
     }
+
+    /// serialize all profiles to a single RON document, in the format of `config.ron`
+    pub fn export(&self) -> Result<String, ron::Error> {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+    }
+
+    /// add the profiles of a RON document in the format of `config.ron`, and write the result with `write_entries`
+    /// imported profiles are renamed if their name is already used, and skipped if they are invalid
+    /// returns the problems found in the imported profiles
+    pub fn import(&mut self, document: &str) -> anyhow::Result<Validation> {
+        let mut imported: Self = ron::from_str(document)?;
+        let mut names: Vec<String> = self.config_list.iter().map(|c| c.name.clone()).collect();
+        for profile in &mut imported.config_list {
+            if profile.name.is_empty() {
+                continue;
+            }
+            let mut name = profile.name.clone();
+            let mut i = 2;
+            while names.contains(&name) {
+                name = format!("{}-{}", profile.name, i);
+                i += 1;
+            }
+            if name != profile.name {
+                info!("Importing profile {} as {}", profile.name, name);
+                profile.name = name.clone();
+            }
+            names.push(name);
+        }
+
+        let validation = imported.sanitize();
+        if imported.config_list.is_empty() {
+            anyhow::bail!("No valid profiles to import");
+        }
+        self.config_list.append(&mut imported.config_list);
+        self.write_entries()
+            .map_err(|err| anyhow::anyhow!("Failed to write the imported config: {:?}", err))?;
+        Ok(validation)
+    }
 }
 
 impl Default for CosmicPanelContainerConfig {
@@ -129,20 +166,21 @@ impl Default for CosmicPanelContainerConfig {
                     size: crate::PanelSize::XS,
                     output: CosmicPanelOuput::All,
                     background: CosmicPanelBackground::ThemeDefault,
-                    plugins_wings: Some((
-                        vec!["com.system76.CosmicAppletWorkspaces".to_string()],
-                        vec![
-                            "com.system76.CosmicAppletAudio".to_string(),
-                            "com.system76.CosmicAppletNetwork".to_string(),
-                            "com.system76.CosmicAppletGraphics".to_string(),
-                            "com.system76.CosmicAppletBattery".to_string(),
-                            "com.system76.CosmicAppletNotifications".to_string(),
-                            "com.system76.CosmicAppletPower".to_string(),
-                            "com.system76.CosmicAppletStatusArea".to_string(),
-                        ],
-                    )),
-                    plugins_center: Some(vec!["com.system76.CosmicAppletTime".to_string()]),
-                    expand_to_edges: true,
+                    mode: crate::PanelMode::Bar {
+                        wings: Some((
+                            vec!["com.system76.CosmicAppletWorkspaces".into()],
+                            vec![
+                                "com.system76.CosmicAppletAudio".into(),
+                                "com.system76.CosmicAppletNetwork".into(),
+                                "com.system76.CosmicAppletGraphics".into(),
+                                "com.system76.CosmicAppletBattery".into(),
+                                "com.system76.CosmicAppletNotifications".into(),
+                                "com.system76.CosmicAppletPower".into(),
+                                "com.system76.CosmicAppletStatusArea".into(),
+                            ],
+                        )),
+                        center: Some(vec!["com.system76.CosmicAppletTime".into()]),
+                    },
                     padding: 2,
                     spacing: 2,
                     border_radius: 0,
@@ -150,6 +188,13 @@ impl Default for CosmicPanelContainerConfig {
                     autohide: None,
                     margin: 0,
                     opacity: 0.9,
+                    priority: 1310,
+                    owns_corners: true,
+                    overrides: Vec::new(),
+                    restart_policy: crate::RestartPolicy::default(),
+                    resource_limits: crate::ResourceLimits::default(),
+                    applet_logs: crate::AppletLogs::default(),
+                    watchdog: crate::Watchdog::default(),
                 },
                 CosmicPanelConfig {
                     name: "Dock".to_string(),
@@ -161,9 +206,10 @@ impl Default for CosmicPanelContainerConfig {
                     size: crate::PanelSize::L,
                     output: CosmicPanelOuput::All,
                     background: CosmicPanelBackground::ThemeDefault,
-                    plugins_wings: None,
-                    plugins_center: Some(vec!["com.system76.CosmicAppList".to_string()]),
-                    expand_to_edges: false,
+                    mode: crate::PanelMode::Dock {
+                        applets: vec!["com.system76.CosmicAppList".into()],
+                        expand_on_maximize: true,
+                    },
                     padding: 4,
                     spacing: 4,
                     border_radius: 12,
@@ -175,6 +221,13 @@ impl Default for CosmicPanelContainerConfig {
                     }),
                     margin: 0,
                     opacity: 0.9,
+                    priority: 300,
+                    owns_corners: false,
+                    overrides: Vec::new(),
+                    restart_policy: crate::RestartPolicy::default(),
+                    resource_limits: crate::ResourceLimits::default(),
+                    applet_logs: crate::AppletLogs::default(),
+                    watchdog: crate::Watchdog::default(),
                 },
             ],
         }
@@ -0,0 +1,91 @@
+//! Differences between two versions of a panel profile, and how a running panel applies them
+
+use crate::CosmicPanelConfig;
+
+/// How a running panel applies a changed value, ordered by the amount of work
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChangeKind {
+    /// the panel is laid out and drawn again, and its changed applets are restarted
+    Redraw,
+    /// the layer surface of the panel is recreated, while its applets keep running
+    Surface,
+    /// the panel is recreated, which restarts its applets
+    Restart,
+}
+
+/// Changed keys of a panel profile
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ConfigDiff {
+    /// changed keys, and how each of them is applied
+    pub changes: Vec<(&'static str, ChangeKind)>,
+}
+
+impl ConfigDiff {
+    /// whether no key changed
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// how all changes are applied together, or None if no key changed
+    pub fn kind(&self) -> Option<ChangeKind> {
+        self.changes.iter().map(|(_, kind)| *kind).max()
+    }
+}
+
+impl CosmicPanelConfig {
+    /// compare the profile with a newer version of it
+    pub fn diff(&self, new: &Self) -> ConfigDiff {
+        use ChangeKind::*;
+
+        // changed applets are started and stopped individually, but a dock is sized differently
+        let mode_change = if self.is_dock() == new.is_dock() {
+            Redraw
+        } else {
+            Surface
+        };
+
+        let mut diff = ConfigDiff::default();
+        macro_rules! compare {
+            ($($key:ident: $kind:expr),* $(,)?) => {
+                // destructured, so every new key has to be classified here
+                let CosmicPanelConfig { $($key),* } = self;
+                $(
+                    if *$key != new.$key {
+                        diff.changes.push((stringify!($key), $kind));
+                    }
+                )*
+            };
+        }
+        compare!(
+            name: Restart,
+            anchor: Surface,
+            anchor_gap: Surface,
+            layer: Surface,
+            keyboard_interactivity: Surface,
+            size: Surface,
+            output: Restart,
+            background: Redraw,
+            mode: mode_change,
+            padding: Redraw,
+            spacing: Redraw,
+            border_radius: Redraw,
+            exclusive_zone: Surface,
+            autohide: Surface,
+            margin: Surface,
+            opacity: Redraw,
+            priority: Surface,
+            owns_corners: Surface,
+            // the overrides of the output are already merged into the other keys
+            overrides: Redraw,
+            // read by the applets of the panel when they exit
+            restart_policy: Redraw,
+            // applied when the applets are spawned
+            resource_limits: Restart,
+            // read by the applets of the panel when they log
+            applet_logs: Redraw,
+            // read by the panel while it checks its applets
+            watchdog: Redraw,
+        );
+        diff
+    }
+}
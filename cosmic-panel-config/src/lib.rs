@@ -1,8 +1,24 @@
 //! Config for cosmic-panel
+mod applet_config;
+#[cfg(feature = "wayland-rs")]
+mod backup;
 #[cfg(feature = "wayland-rs")]
 mod container_config;
+#[cfg(feature = "wayland-rs")]
+mod diff;
+#[cfg(feature = "wayland-rs")]
+pub mod migration;
+mod output_config;
 mod panel_config;
+#[cfg(feature = "wayland-rs")]
+mod validation;
 
+pub use applet_config::*;
 #[cfg(feature = "wayland-rs")]
 pub use container_config::*;
+#[cfg(feature = "wayland-rs")]
+pub use diff::*;
+pub use output_config::*;
 pub use panel_config::*;
+#[cfg(feature = "wayland-rs")]
+pub use validation::*;
@@ -0,0 +1,365 @@
+//! Migrations of the panel config between schema versions
+//!
+//! Each config is stored by cosmic-config as one RON file per key in `$XDG_CONFIG_HOME/cosmic/<name>/v<version>/`.
+//! When the directory for the current version is empty, the newest older version found is migrated key by key
+//! and written to the current version. Older and newer versions are never modified, so downgrading keeps working.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use tracing::{error, info, warn};
+
+use crate::{AppletEntry, CosmicPanelBackground, PanelMode, NAME, VERSION};
+
+/// RON encoded values of a config, by key
+type Keys = BTreeMap<String, String>;
+
+/// Migrations of a panel profile, indexed by the version which they migrate from, starting at 1
+const PROFILE_MIGRATIONS: &[fn(&mut Keys)] =
+    &[profile_v1_to_v2, profile_v2_to_v3, profile_v3_to_v4];
+
+/// Keys of the container config
+const CONTAINER_KEYS: &[&str] = &["entries"];
+
+/// Keys of a panel profile, which should match the fields of `CosmicPanelConfig`
+const PROFILE_KEYS: &[&str] = &[
+    "name",
+    "anchor",
+    "anchor_gap",
+    "layer",
+    "keyboard_interactivity",
+    "size",
+    "output",
+    "background",
+    "mode",
+    "padding",
+    "spacing",
+    "border_radius",
+    "exclusive_zone",
+    "autohide",
+    "margin",
+    "opacity",
+    "priority",
+    "owns_corners",
+    "overrides",
+    "restart_policy",
+    "resource_limits",
+    "applet_logs",
+    "watchdog",
+];
+
+/// Migrate the container config and each of its panel profiles to the current version,
+/// and log keys which are unknown to this version.
+pub fn migrate() {
+    let Some(root) = config_root() else {
+        warn!("Failed to find the config directory, skipping migrations");
+        return;
+    };
+
+    migrate_config(&root, NAME, |_, _| {});
+    log_unknown_keys(&root, NAME, CONTAINER_KEYS);
+
+    let Some(entries) = read_keys(&version_dir(&root, NAME, VERSION))
+        .get("entries")
+        .and_then(|entries| ron::from_str::<Vec<String>>(entries).ok())
+    else {
+        return;
+    };
+    for entry in entries {
+        let name = format!("{}.{}", NAME, entry);
+        migrate_config(&root, &name, |version, keys| {
+            PROFILE_MIGRATIONS[version as usize - 1](keys)
+        });
+        log_unknown_keys(&root, &name, PROFILE_KEYS);
+    }
+}
+
+fn migrate_config(root: &Path, name: &str, migration: impl Fn(u64, &mut Keys)) {
+    if let Some(newer) = versions(root, name).into_iter().find(|v| *v > VERSION) {
+        warn!(
+            "{} has a config for the newer version {}, which is left untouched. Using version {}.",
+            name, newer, VERSION
+        );
+    }
+
+    let current_dir = version_dir(root, name, VERSION);
+    if !read_keys(&current_dir).is_empty() {
+        return;
+    }
+    let Some(from) = source_version(versions(root, name)) else {
+        return;
+    };
+    let mut keys = read_keys(&version_dir(root, name, from));
+    if keys.is_empty() {
+        return;
+    }
+
+    info!("Migrating {} from version {} to {}", name, from, VERSION);
+    for version in from..VERSION {
+        migration(version, &mut keys);
+    }
+
+    if let Err(err) = fs::create_dir_all(&current_dir) {
+        error!("Failed to create {:?}: {}", current_dir, err);
+        return;
+    }
+    for (key, value) in keys {
+        if let Err(err) = fs::write(current_dir.join(&key), value) {
+            error!("Failed to write migrated key {} of {}: {}", key, name, err);
+        }
+    }
+}
+
+fn log_unknown_keys(root: &Path, name: &str, known: &[&str]) {
+    for key in read_keys(&version_dir(root, name, VERSION)).keys() {
+        if !known.contains(&key.as_str()) {
+            warn!(
+                "Ignoring unknown key {} of {}, it may have been written by a newer version",
+                key, name
+            );
+        }
+    }
+}
+
+/// In version 1, the theme default background carried the opacity of the panel.
+fn profile_v1_to_v2(keys: &mut Keys) {
+    #[derive(Deserialize)]
+    enum LegacyBackground {
+        ThemeDefault(Option<f32>),
+    }
+
+    let Some(Ok(LegacyBackground::ThemeDefault(opacity))) = keys
+        .get("background")
+        .map(|background| ron::from_str::<LegacyBackground>(background))
+    else {
+        return;
+    };
+    if let Ok(background) = ron::to_string(&CosmicPanelBackground::ThemeDefault) {
+        keys.insert("background".to_string(), background);
+    }
+    if let Some(opacity) = opacity.and_then(|o| ron::to_string(&o).ok()) {
+        keys.entry("opacity".to_string()).or_insert(opacity);
+    }
+}
+
+/// In version 2, the priority of a panel was derived from its other keys and its name.
+/// The derived priority is kept, so panels are stacked as before,
+/// and panels which expand to the edges of the output keep the corners they took by being created first.
+fn profile_v2_to_v3(keys: &mut Keys) {
+    fn get<T: for<'de> Deserialize<'de>>(keys: &Keys, key: &str) -> Option<T> {
+        keys.get(key).and_then(|value| ron::from_str(value).ok())
+    }
+
+    let expand_to_edges = get::<bool>(keys, "expand_to_edges").unwrap_or(true);
+    let mut priority = if expand_to_edges { 1000 } else { 0 };
+    if get::<u16>(keys, "margin") == Some(0) {
+        priority += 200;
+    }
+    if get::<bool>(keys, "anchor_gap") == Some(false) {
+        priority += 100;
+    }
+    if get::<String>(keys, "name").is_some_and(|name| name.to_lowercase().contains("panel")) {
+        priority += 10;
+    }
+
+    if let Ok(priority) = ron::to_string(&priority) {
+        keys.entry("priority".to_string()).or_insert(priority);
+    }
+    if let Ok(owns_corners) = ron::to_string(&expand_to_edges) {
+        keys.entry("owns_corners".to_string())
+            .or_insert(owns_corners);
+    }
+}
+
+/// In version 3, the mode of the panel was inferred from `expand_to_edges`,
+/// and the wings of a panel which doesn't expand to the edges were merged into its center.
+/// A dock gets the applets in the order it showed them.
+fn profile_v3_to_v4(keys: &mut Keys) {
+    fn parse<T: for<'de> Deserialize<'de>>(
+        keys: &Keys,
+        key: &str,
+    ) -> Result<Option<T>, ron::error::SpannedError> {
+        keys.get(key).map(|value| ron::from_str(value)).transpose()
+    }
+
+    let (expand_to_edges, wings, center) = match (
+        parse::<bool>(keys, "expand_to_edges"),
+        parse::<Option<(Vec<AppletEntry>, Vec<AppletEntry>)>>(keys, "plugins_wings"),
+        parse::<Option<Vec<AppletEntry>>>(keys, "plugins_center"),
+    ) {
+        (Ok(expand_to_edges), Ok(wings), Ok(center)) => (
+            expand_to_edges.unwrap_or(true),
+            wings.flatten(),
+            center.flatten(),
+        ),
+        (Err(err), _, _) | (_, Err(err), _) | (_, _, Err(err)) => {
+            warn!("Failed to migrate the mode of the panel: {}", err);
+            return;
+        }
+    };
+
+    let mode = if expand_to_edges {
+        PanelMode::Bar { wings, center }
+    } else {
+        let (left, right) = wings.unwrap_or_default();
+        PanelMode::Dock {
+            applets: left
+                .into_iter()
+                .chain(center.unwrap_or_default())
+                .chain(right)
+                .collect(),
+            expand_on_maximize: true,
+        }
+    };
+    match ron::to_string(&mode) {
+        Ok(mode) => {
+            keys.insert("mode".to_string(), mode);
+            for key in ["plugins_wings", "plugins_center", "expand_to_edges"] {
+                keys.remove(key);
+            }
+        }
+        Err(err) => warn!("Failed to migrate the mode of the panel: {}", err),
+    }
+}
+
+pub(crate) fn config_root() -> Option<PathBuf> {
+    xdg::BaseDirectories::new()
+        .ok()
+        .map(|dirs| dirs.get_config_home().join("cosmic"))
+}
+
+pub(crate) fn version_dir(root: &Path, name: &str, version: u64) -> PathBuf {
+    root.join(name).join(format!("v{}", version))
+}
+
+/// versions of the config which exist in the user's config directory
+fn versions(root: &Path, name: &str) -> Vec<u64> {
+    let Ok(dir) = fs::read_dir(root.join(name)) else {
+        return Vec::new();
+    };
+    dir.filter_map(|entry| {
+        let name = entry.ok()?.file_name();
+        name.to_str()?.strip_prefix('v')?.parse().ok()
+    })
+    .collect()
+}
+
+/// newest of the versions which is older than the current version
+fn source_version(versions: impl IntoIterator<Item = u64>) -> Option<u64> {
+    versions
+        .into_iter()
+        // versions start at 1, so there is nothing to migrate from a `v0` directory
+        .filter(|v| (1..VERSION).contains(v))
+        .max()
+}
+
+pub(crate) fn read_keys(dir: &Path) -> Keys {
+    let Ok(dir) = fs::read_dir(dir) else {
+        return Keys::new();
+    };
+    dir.filter_map(|entry| {
+        let entry = entry.ok()?;
+        if !entry.file_type().ok()?.is_file() {
+            return None;
+        }
+        let key = entry.file_name().to_str()?.to_string();
+        // skip temporary files of atomic writes
+        if key.starts_with('.') {
+            return None;
+        }
+        Some((key, fs::read_to_string(entry.path()).ok()?))
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_version_is_newest_older_version() {
+        assert_eq!(source_version([1, VERSION - 1]), Some(VERSION - 1));
+        assert_eq!(source_version([VERSION - 1, 1]), Some(VERSION - 1));
+        assert_eq!(
+            source_version([VERSION + 1, VERSION, VERSION - 1]),
+            Some(VERSION - 1)
+        );
+    }
+
+    #[test]
+    fn source_version_ignores_current_and_newer_versions() {
+        assert_eq!(source_version([]), None);
+        assert_eq!(source_version([VERSION]), None);
+        assert_eq!(source_version([VERSION + 1]), None);
+    }
+
+    #[test]
+    fn source_version_ignores_v0() {
+        assert_eq!(source_version([0]), None);
+        assert_eq!(source_version([0, VERSION]), None);
+        assert_eq!(source_version([0, 1]), Some(1));
+    }
+
+    fn keys(values: &[(&str, &str)]) -> Keys {
+        values
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn v2_to_v3_derives_priority() {
+        let mut panel = keys(&[
+            ("name", "\"Panel\""),
+            ("expand_to_edges", "true"),
+            ("margin", "0"),
+            ("anchor_gap", "false"),
+        ]);
+        profile_v2_to_v3(&mut panel);
+        assert_eq!(panel["priority"], "1310");
+        assert_eq!(panel["owns_corners"], "true");
+
+        let mut dock = keys(&[
+            ("name", "\"Dock\""),
+            ("expand_to_edges", "false"),
+            ("margin", "4"),
+            ("anchor_gap", "true"),
+        ]);
+        profile_v2_to_v3(&mut dock);
+        assert_eq!(dock["priority"], "0");
+        assert_eq!(dock["owns_corners"], "false");
+
+        let mut floating_panel = keys(&[
+            ("name", "\"my-panel\""),
+            ("expand_to_edges", "false"),
+            ("margin", "0"),
+        ]);
+        profile_v2_to_v3(&mut floating_panel);
+        assert_eq!(floating_panel["priority"], "210");
+        assert_eq!(floating_panel["owns_corners"], "false");
+    }
+
+    #[test]
+    fn v2_to_v3_defaults_to_expanding_to_edges() {
+        let mut profile = Keys::new();
+        profile_v2_to_v3(&mut profile);
+        assert_eq!(profile["priority"], "1000");
+        assert_eq!(profile["owns_corners"], "true");
+    }
+
+    #[test]
+    fn v2_to_v3_keeps_existing_keys() {
+        let mut profile = keys(&[
+            ("expand_to_edges", "true"),
+            ("priority", "5"),
+            ("owns_corners", "false"),
+        ]);
+        profile_v2_to_v3(&mut profile);
+        assert_eq!(profile["priority"], "5");
+        assert_eq!(profile["owns_corners"], "false");
+    }
+}
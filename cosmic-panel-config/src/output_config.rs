@@ -0,0 +1,188 @@
+//! Selection of the outputs of a cosmic panel
+
+use serde::{Deserialize, Serialize};
+
+/// Connector name prefixes of the built-in displays of laptops
+const INTERNAL_CONNECTORS: &[&str] = &["eDP", "LVDS", "DSI"];
+
+/// Properties of an output which can be matched by an `OutputSelector`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct OutputMatchInfo {
+    /// connector name, for example "DP-1"
+    pub name: String,
+    /// manufacturer of the display
+    pub make: String,
+    /// model of the display
+    pub model: String,
+    /// serial number of the display, if it is known
+    pub serial: Option<String>,
+}
+
+impl OutputMatchInfo {
+    /// whether the output is the built-in display of a laptop
+    pub fn is_internal(&self) -> bool {
+        INTERNAL_CONNECTORS
+            .iter()
+            .any(|prefix| self.name.starts_with(prefix))
+    }
+}
+
+/// Matches outputs by their properties
+/// Strings are glob patterns, where `*` matches any sequence of characters and `?` matches any character
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub enum OutputSelector {
+    /// connector name, for example "DP-*"
+    Name(String),
+    /// manufacturer of the display
+    Make(String),
+    /// model of the display
+    Model(String),
+    /// serial number of the display
+    Serial(String),
+    /// the built-in display of a laptop
+    Internal,
+    /// outputs matching every selector of the list
+    AllOf(Vec<OutputSelector>),
+}
+
+impl OutputSelector {
+    /// whether the output is matched by the selector
+    pub fn matches(&self, output: &OutputMatchInfo) -> bool {
+        match self {
+            OutputSelector::Name(p) => glob_match(p, &output.name),
+            OutputSelector::Make(p) => glob_match(p, &output.make),
+            OutputSelector::Model(p) => glob_match(p, &output.model),
+            OutputSelector::Serial(p) => output.serial.as_ref().is_some_and(|s| glob_match(p, s)),
+            OutputSelector::Internal => output.is_internal(),
+            OutputSelector::AllOf(selectors) => selectors.iter().all(|s| s.matches(output)),
+        }
+    }
+}
+
+/// whether the pattern contains glob wildcards
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// match the text against a glob pattern, where `*` matches any sequence of characters and `?` matches any character
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // position of the last `*` in the pattern, and of the text it was matched at
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // let the last `*` match one more character
+                Some((star_p, star_t)) => {
+                    backtrack = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CosmicPanelOuput;
+
+    fn output(name: &str, serial: Option<&str>) -> OutputMatchInfo {
+        OutputMatchInfo {
+            name: name.to_string(),
+            make: "Dell Inc.".to_string(),
+            model: "U2720Q".to_string(),
+            serial: serial.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn glob_match_literal() {
+        assert!(glob_match("DP-1", "DP-1"));
+        assert!(!glob_match("DP-1", "DP-10"));
+        assert!(!glob_match("DP-10", "DP-1"));
+        assert!(glob_match("", ""));
+        assert!(!glob_match("", "DP-1"));
+    }
+
+    #[test]
+    fn glob_match_star() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "HDMI-A-1"));
+        assert!(glob_match("HDMI-*", "HDMI-A-1"));
+        assert!(glob_match("HDMI-*", "HDMI-"));
+        assert!(!glob_match("HDMI-*", "DP-1"));
+        assert!(glob_match("*-1", "HDMI-A-1"));
+        assert!(glob_match("D*l*", "Dell Inc."));
+        // the last `*` backtracks past a partial match
+        assert!(glob_match("*A-1", "HDMI-A-A-1"));
+        assert!(!glob_match("*A-2", "HDMI-A-1"));
+    }
+
+    #[test]
+    fn glob_match_question_mark() {
+        assert!(glob_match("DP-?", "DP-1"));
+        assert!(!glob_match("DP-?", "DP-"));
+        assert!(!glob_match("DP-?", "DP-10"));
+        assert!(glob_match("DP-??", "DP-10"));
+        assert!(glob_match("?*", "eDP-1"));
+        assert!(!glob_match("?*", ""));
+    }
+
+    #[test]
+    fn is_glob_detects_wildcards() {
+        assert!(is_glob("DP-*"));
+        assert!(is_glob("DP-?"));
+        assert!(!is_glob("DP-1"));
+    }
+
+    #[test]
+    fn selector_matches() {
+        let dp = output("DP-1", Some("ABC123"));
+        assert!(OutputSelector::Name("DP-*".to_string()).matches(&dp));
+        assert!(OutputSelector::Make("Dell*".to_string()).matches(&dp));
+        assert!(OutputSelector::Model("U27??Q".to_string()).matches(&dp));
+        assert!(OutputSelector::Serial("ABC*".to_string()).matches(&dp));
+        assert!(!OutputSelector::Internal.matches(&dp));
+        assert!(OutputSelector::Internal.matches(&output("eDP-1", None)));
+        // an output without a known serial matches no serial pattern
+        assert!(!OutputSelector::Serial("*".to_string()).matches(&output("DP-1", None)));
+        assert!(OutputSelector::AllOf(vec![
+            OutputSelector::Name("DP-*".to_string()),
+            OutputSelector::Make("Dell*".to_string()),
+        ])
+        .matches(&dp));
+        assert!(!OutputSelector::AllOf(vec![
+            OutputSelector::Name("DP-*".to_string()),
+            OutputSelector::Internal,
+        ])
+        .matches(&dp));
+    }
+
+    #[test]
+    fn all_except_excludes_matched_outputs() {
+        let panel_output = CosmicPanelOuput::AllExcept(vec![
+            OutputSelector::Internal,
+            OutputSelector::Serial("ABC123".to_string()),
+        ]);
+        assert!(!panel_output.matches(&output("eDP-1", None)));
+        assert!(!panel_output.matches(&output("DP-1", Some("ABC123"))));
+        assert!(panel_output.matches(&output("DP-1", Some("XYZ789"))));
+        assert!(panel_output.matches(&output("HDMI-A-1", None)));
+        assert!(CosmicPanelOuput::AllExcept(Vec::new()).matches(&output("DP-1", None)));
+    }
+}
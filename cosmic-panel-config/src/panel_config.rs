@@ -1,16 +1,21 @@
 //! Config for cosmic-panel
 
-use std::{fmt::Display, ops::Range, str::FromStr, time::Duration};
+use std::{cmp::Ordering, fmt::Display, ops::Range, str::FromStr, time::Duration};
 
 use anyhow::bail;
-use cosmic_config::{cosmic_config_derive::CosmicConfigEntry, Config, CosmicConfigEntry};
+use cosmic_config::{
+    cosmic_config_derive::CosmicConfigEntry, Config, ConfigSet, CosmicConfigEntry,
+};
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "wayland-rs")]
 use wayland_protocols_wlr::layer_shell::v1::client::{zwlr_layer_shell_v1, zwlr_layer_surface_v1};
 #[cfg(feature = "wayland-rs")]
 use xdg_shell_wrapper_config::{KeyboardInteractivity, Layer, WrapperConfig, WrapperOutput};
 
-use crate::{NAME, VERSION};
+use crate::{
+    glob_match, AppletEntry, AppletLogs, OutputMatchInfo, OutputSelector, ResourceLimits,
+    RestartPolicy, Watchdog, NAME, VERSION,
+};
 
 /// Edge to which the panel is anchored
 #[derive(Debug, Deserialize, Serialize, Copy, Clone, PartialEq, Eq)]
@@ -26,6 +31,18 @@ pub enum PanelAnchor {
     Bottom,
 }
 
+impl PanelAnchor {
+    /// rank of the edge when ordering panels of equal priority, horizontal panels take the corners first
+    fn stacking_rank(&self) -> u8 {
+        match self {
+            PanelAnchor::Top => 0,
+            PanelAnchor::Bottom => 1,
+            PanelAnchor::Left => 2,
+            PanelAnchor::Right => 3,
+        }
+    }
+}
+
 impl Display for PanelAnchor {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -111,6 +128,35 @@ pub enum PanelSize {
     L,
     /// XL
     XL,
+    /// exact thickness of the panel and size of applet icons in pixels
+    Custom {
+        /// thickness of the panel
+        thickness: u32,
+        /// size of applet icons
+        icon_size: u32,
+    },
+}
+
+impl PanelSize {
+    /// get the thickness of the panel if it has been configured explicitly
+    pub fn fixed_thickness(&self) -> Option<u32> {
+        match self {
+            PanelSize::Custom { thickness, .. } => Some(*thickness),
+            _ => None,
+        }
+    }
+
+    /// get the largest thickness of the panel
+    pub fn max_thickness(&self) -> u32 {
+        match self {
+            PanelSize::XS => 60,
+            PanelSize::S => 80,
+            PanelSize::M => 100,
+            PanelSize::L => 120,
+            PanelSize::XL => 140,
+            PanelSize::Custom { thickness, .. } => *thickness,
+        }
+    }
 }
 
 impl Display for PanelSize {
@@ -121,6 +167,10 @@ impl Display for PanelSize {
             PanelSize::M => write!(f, "M"),
             PanelSize::L => write!(f, "L"),
             PanelSize::XL => write!(f, "XL"),
+            PanelSize::Custom {
+                thickness,
+                icon_size,
+            } => write!(f, "Custom(thickness:{},icon_size:{})", thickness, icon_size),
         }
     }
 }
@@ -135,12 +185,35 @@ impl FromStr for PanelSize {
             "M" => Ok(Self::M),
             "L" => Ok(Self::L),
             "XL" => Ok(Self::XL),
-            _ => Err(anyhow::anyhow!("Not a valid PanelSize")),
+            s => {
+                let Some((thickness, icon_size)) = s
+                    .strip_prefix("Custom(")
+                    .and_then(|s| s.strip_suffix(')'))
+                    .and_then(|s| s.split_once(','))
+                else {
+                    bail!("Not a valid PanelSize");
+                };
+                // the field names are optional, like in the RON of the config
+                let field = |value: &str, name: &str| {
+                    let value = value.trim();
+                    value
+                        .strip_prefix(name)
+                        .and_then(|v| v.trim_start().strip_prefix(':'))
+                        .unwrap_or(value)
+                        .trim()
+                        .parse::<u32>()
+                };
+                Ok(Self::Custom {
+                    thickness: field(thickness, "thickness")?,
+                    icon_size: field(icon_size, "icon_size")?,
+                })
+            }
         }
     }
 }
 
 /// configurable backgrounds for the cosmic panel
+/// the alpha of every background is multiplied by the opacity of the panel
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub enum CosmicPanelBackground {
@@ -150,14 +223,80 @@ pub enum CosmicPanelBackground {
     Dark,
     /// theme default light
     Light,
-    /// RGBA
+    /// RGB
     Color([f32; 3]),
+    /// RGBA
+    Rgba([f32; 4]),
+    /// CSS color, for example "#3daee9cc" or "rgb(61 174 233 / 80%)"
+    Css(String),
+    /// linear gradient along the length of the panel, from the left or top edge
+    Gradient(Vec<GradientStop>),
+    /// image file drawn behind the applets
+    Image {
+        /// path to the image file
+        path: String,
+        /// how the image is fitted to the panel
+        #[serde(default)]
+        scaling: ImageScaling,
+    },
+}
+
+impl CosmicPanelBackground {
+    /// get the color of a single color background, or None for theme colors, gradients and images
+    pub fn color(&self) -> Option<[f32; 4]> {
+        match self {
+            CosmicPanelBackground::Color(c) => Some([c[0], c[1], c[2], 1.0]),
+            CosmicPanelBackground::Rgba(c) => Some(*c),
+            CosmicPanelBackground::Css(c) => parse_color(c),
+            _ => None,
+        }
+    }
+
+    /// whether the background is a single color
+    pub fn is_solid(&self) -> bool {
+        !matches!(
+            self,
+            CosmicPanelBackground::Gradient(_) | CosmicPanelBackground::Image { .. }
+        )
+    }
+}
+
+/// a color stop of a gradient background
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GradientStop {
+    /// position of the stop along the panel, between 0 and 1
+    pub position: f32,
+    /// CSS color of the stop
+    pub color: String,
+}
+
+/// how an image background is fitted to the panel
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(deny_unknown_fields)]
+pub enum ImageScaling {
+    /// scale the image to the size of the panel, ignoring its aspect ratio
+    #[default]
+    Stretch,
+    /// scale the image to cover the panel, cropping it
+    Fill,
+    /// scale the image to fit in the panel, leaving the rest transparent
+    Fit,
+    /// center the image without scaling it
+    Center,
+    /// repeat the image without scaling it
+    Tile,
+}
+
+/// parse a CSS color to RGBA
+pub fn parse_color(color: &str) -> Option<[f32; 4]> {
+    let c = csscolorparser::parse(color).ok()?;
+    Some([c.r as f32, c.g as f32, c.b as f32, c.a as f32])
 }
 
 // TODO configurable interpolation type?
 /// configurable autohide behavior
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
-#[serde(deny_unknown_fields)]
 pub struct AutoHide {
     /// time in milliseconds without pointer focus before hiding
     pub wait_time: u32,
@@ -178,16 +317,159 @@ impl Default for AutoHide {
     }
 }
 
+/// Layout of the panel and its applets
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub enum PanelMode {
+    /// spans the length of its edge, with applets in the wings at either end and in the center
+    Bar {
+        /// applets on the left / top and right / bottom of the panel
+        #[serde(default)]
+        wings: Option<(Vec<AppletEntry>, Vec<AppletEntry>)>,
+        /// applets in the center of the panel
+        #[serde(default)]
+        center: Option<Vec<AppletEntry>>,
+    },
+    /// sized to its applets, which are centered on its edge
+    Dock {
+        /// applets of the dock
+        #[serde(default)]
+        applets: Vec<AppletEntry>,
+        /// whether the dock spans its edge like a bar while a window is maximized on its output
+        #[serde(default = "expand_on_maximize_default")]
+        expand_on_maximize: bool,
+    },
+}
+
+fn expand_on_maximize_default() -> bool {
+    true
+}
+
+impl Default for PanelMode {
+    fn default() -> Self {
+        PanelMode::Bar {
+            wings: None,
+            center: None,
+        }
+    }
+}
+
+impl PanelMode {
+    /// the mode of the panel while a window is maximized on its output, where it spans its edge
+    /// None if the panel is a dock which is left floating over the window
+    pub fn maximized(&self) -> Option<PanelMode> {
+        match self {
+            PanelMode::Bar { .. } => Some(self.clone()),
+            PanelMode::Dock {
+                applets,
+                expand_on_maximize: true,
+            } => Some(PanelMode::Bar {
+                wings: None,
+                center: Some(applets.clone()),
+            }),
+            PanelMode::Dock { .. } => None,
+        }
+    }
+
+    /// get the applets of all lists of the panel
+    pub fn applets(&self) -> Vec<&AppletEntry> {
+        match self {
+            PanelMode::Bar { wings, center } => wings
+                .iter()
+                .flat_map(|(left, right)| left.iter().chain(right.iter()))
+                .chain(center.iter().flatten())
+                .collect(),
+            PanelMode::Dock { applets, .. } => applets.iter().collect(),
+        }
+    }
+
+    /// get the applets of all lists of the panel, to modify them
+    pub fn applets_mut(&mut self) -> Vec<&mut AppletEntry> {
+        match self {
+            PanelMode::Bar { wings, center } => wings
+                .iter_mut()
+                .flat_map(|(left, right)| left.iter_mut().chain(right.iter_mut()))
+                .chain(center.iter_mut().flatten())
+                .collect(),
+            PanelMode::Dock { applets, .. } => applets.iter_mut().collect(),
+        }
+    }
+
+    /// remove the applets with the desktop file or instance ids from the panel
+    fn hide_applets(&mut self, ids: &[String]) {
+        let retain = |applets: &mut Vec<AppletEntry>| {
+            applets.retain(|a| !ids.iter().any(|id| *id == a.id() || *id == a.instance_id()));
+        };
+        match self {
+            PanelMode::Bar { wings, center } => {
+                if let Some((left, right)) = wings.as_mut() {
+                    retain(left);
+                    retain(right);
+                }
+                if let Some(center) = center.as_mut() {
+                    retain(center);
+                }
+            }
+            PanelMode::Dock { applets, .. } => retain(applets),
+        }
+    }
+}
+
+/// Values of a panel profile which are replaced on the outputs matched by a selector
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PanelOverride {
+    /// size of the panel
+    #[serde(default)]
+    pub size: Option<PanelSize>,
+    /// padding around the panel
+    #[serde(default)]
+    pub padding: Option<u32>,
+    /// space between panel plugins
+    #[serde(default)]
+    pub spacing: Option<u32>,
+    /// margin between the panel and the edge of the output
+    #[serde(default)]
+    pub margin: Option<u16>,
+    /// radius of the corners of the panel
+    #[serde(default)]
+    pub border_radius: Option<u32>,
+    /// opacity of the panel
+    #[serde(default)]
+    pub opacity: Option<f32>,
+    /// desktop file ids of applets which are not shown
+    #[serde(default)]
+    pub hidden_applets: Vec<String>,
+}
+
 /// Configuration for the panel's ouput
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
 pub enum CosmicPanelOuput {
     /// show panel on all outputs
     All,
-    /// show panel on the active output
+    /// show panel on the output with keyboard or pointer focus, following it to other outputs
     Active,
-    /// show panel on a specific output
+    /// show panel on the outputs whose connector name matches the glob pattern, for example "DP-1" or "HDMI-*"
     Name(String),
+    /// show panel on the outputs matched by any of the selectors
+    Any(Vec<OutputSelector>),
+    /// show panel on all outputs except those matched by any of the selectors
+    AllExcept(Vec<OutputSelector>),
+}
+
+impl CosmicPanelOuput {
+    /// whether the panel should be shown on the output
+    /// `Active` matches no output, as it depends on focus
+    pub fn matches(&self, output: &OutputMatchInfo) -> bool {
+        match self {
+            CosmicPanelOuput::All => true,
+            CosmicPanelOuput::Active => false,
+            CosmicPanelOuput::Name(pattern) => glob_match(pattern, &output.name),
+            CosmicPanelOuput::Any(selectors) => selectors.iter().any(|s| s.matches(output)),
+            CosmicPanelOuput::AllExcept(selectors) => !selectors.iter().any(|s| s.matches(output)),
+        }
+    }
 }
 
 impl Display for CosmicPanelOuput {
@@ -196,6 +478,9 @@ impl Display for CosmicPanelOuput {
             CosmicPanelOuput::All => write!(f, "All"),
             CosmicPanelOuput::Active => write!(f, "Active"),
             CosmicPanelOuput::Name(n) => write!(f, "Name({})", n),
+            CosmicPanelOuput::Any(_) | CosmicPanelOuput::AllExcept(_) => {
+                write!(f, "{}", ron::to_string(self).map_err(|_| std::fmt::Error)?)
+            }
         }
     }
 }
@@ -210,7 +495,7 @@ impl FromStr for CosmicPanelOuput {
             s if s.len() >= 6 && &s[..5] == "Name(" && s.ends_with(')') => {
                 Ok(Self::Name(s[5..s.len() - 1].to_string()))
             }
-            _ => bail!("Failed to parse output."),
+            s => ron::from_str(s).map_err(|_| anyhow::anyhow!("Failed to parse output.")),
         }
     }
 }
@@ -221,16 +506,19 @@ impl Into<WrapperOutput> for CosmicPanelOuput {
         match self {
             CosmicPanelOuput::All => WrapperOutput::All,
             CosmicPanelOuput::Active => WrapperOutput::Name(vec![]),
-            CosmicPanelOuput::Name(n) => WrapperOutput::Name(vec![n]),
+            CosmicPanelOuput::Name(n) if !crate::is_glob(&n) => WrapperOutput::Name(vec![n]),
+            // the panel filters the outputs itself
+            CosmicPanelOuput::Name(_)
+            | CosmicPanelOuput::Any(_)
+            | CosmicPanelOuput::AllExcept(_) => WrapperOutput::All,
         }
     }
 }
 
 #[cfg(feature = "wayland-rs")]
-// TODO refactor to have separate dock mode config & panel mode config
 /// Config structure for the cosmic panel
+/// Keys added here should also be added to the known keys of the migrations.
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, CosmicConfigEntry)]
-#[serde(deny_unknown_fields)]
 pub struct CosmicPanelConfig {
     /// profile name for this config, should be unique
     pub name: String,
@@ -248,12 +536,8 @@ pub struct CosmicPanelConfig {
     pub output: CosmicPanelOuput,
     /// customized background, or
     pub background: CosmicPanelBackground,
-    /// list of plugins on the left / top and right / bottom of the panel
-    pub plugins_wings: Option<(Vec<String>, Vec<String>)>,
-    /// list of plugins in the center of the panel
-    pub plugins_center: Option<Vec<String>>,
-    /// whether the panel should stretch to the edges of output
-    pub expand_to_edges: bool,
+    /// whether the panel is a bar or a dock, with the applets and settings of the mode
+    pub mode: PanelMode,
     /// padding around the panel
     pub padding: u32,
     /// space between panel plugins
@@ -268,6 +552,29 @@ pub struct CosmicPanelConfig {
     pub margin: u16,
     /// opacity of the panel
     pub opacity: f32,
+    /// panels with a higher priority are created first, and are closer to the edge than other panels on the same edge
+    #[serde(default)]
+    pub priority: u32,
+    /// whether the panel takes the corners it shares with perpendicular panels, which then end at its edge
+    /// panels which own their corners are created before panels which don't, regardless of priority
+    #[serde(default)]
+    pub owns_corners: bool,
+    /// output selectors and the values which replace those of the profile on the matched outputs
+    /// the overrides of every matching selector are merged in order, so later overrides take precedence
+    #[serde(default)]
+    pub overrides: Vec<(OutputSelector, PanelOverride)>,
+    /// how the applets of the panel are restarted after they exit, unless an applet has its own restart policy
+    #[serde(default)]
+    pub restart_policy: RestartPolicy,
+    /// resource limits of the applets of the panel, for the limits which an applet doesn't set itself
+    #[serde(default)]
+    pub resource_limits: ResourceLimits,
+    /// how the output of the applets of the panel is logged
+    #[serde(default)]
+    pub applet_logs: AppletLogs,
+    /// how the applets of the panel are checked for a window and for answering pings, unless an applet has its own watchdog
+    #[serde(default)]
+    pub watchdog: Watchdog,
 }
 
 #[cfg(feature = "wayland-rs")]
@@ -282,9 +589,7 @@ impl Default for CosmicPanelConfig {
             size: PanelSize::M,
             output: CosmicPanelOuput::All,
             background: CosmicPanelBackground::ThemeDefault,
-            plugins_wings: Default::default(),
-            plugins_center: Default::default(),
-            expand_to_edges: true,
+            mode: PanelMode::default(),
             padding: 4,
             spacing: 4,
             exclusive_zone: true,
@@ -292,10 +597,20 @@ impl Default for CosmicPanelConfig {
             border_radius: 8,
             margin: 4,
             opacity: 0.8,
+            priority: 0,
+            owns_corners: false,
+            overrides: Vec::new(),
+            restart_policy: RestartPolicy::default(),
+            resource_limits: ResourceLimits::default(),
+            applet_logs: AppletLogs::default(),
+            watchdog: Watchdog::default(),
         }
     }
 }
 
+/// smallest thickness of a panel without a custom size
+pub(crate) const MIN_THICKNESS: u32 = 8;
+
 #[cfg(feature = "wayland-rs")]
 impl CosmicPanelConfig {
     /// get applet icon dimensions
@@ -306,23 +621,49 @@ impl CosmicPanelConfig {
             PanelSize::M => 36,
             PanelSize::L => 48,
             PanelSize::XL => 64,
+            PanelSize::Custom { icon_size, .. } => *icon_size,
         }
     }
 
     /// get the priority of the panel
     /// higher priority panels will be created first and given more space when competing for space
     pub fn get_priority(&self) -> u32 {
-        let mut priority = if self.expand_to_edges() { 1000 } else { 0 };
-        if self.margin == 0 {
-            priority += 200;
-        }
-        if !self.anchor_gap {
-            priority += 100;
-        }
-        if self.name.to_lowercase().contains("panel") {
-            priority += 10;
+        self.priority
+    }
+
+    /// order in which panels sharing an output are created
+    /// the compositor reserves the exclusive zones of layer surfaces in the order they are created,
+    /// so panels which come first are closer to their edge and take the corners from perpendicular panels.
+    /// Panels which own their corners come first, then panels by descending priority.
+    /// Ties are broken by the edge and the name, so the order doesn't depend on the order of the entries.
+    pub fn cmp_stacking(&self, other: &Self) -> Ordering {
+        other
+            .owns_corners
+            .cmp(&self.owns_corners)
+            .then_with(|| other.priority.cmp(&self.priority))
+            .then_with(|| {
+                self.anchor
+                    .stacking_rank()
+                    .cmp(&other.anchor.stacking_rank())
+            })
+            .then_with(|| self.name.cmp(&other.name))
+    }
+
+    /// get the profile with the overrides of the output merged over it
+    pub fn for_output(&self, output: &OutputMatchInfo) -> CosmicPanelConfig {
+        let mut config = self.clone();
+        for (_, o) in self.overrides.iter().filter(|(s, _)| s.matches(output)) {
+            if let Some(size) = o.size.as_ref() {
+                config.size = size.clone();
+            }
+            config.padding = o.padding.unwrap_or(config.padding);
+            config.spacing = o.spacing.unwrap_or(config.spacing);
+            config.margin = o.margin.unwrap_or(config.margin);
+            config.border_radius = o.border_radius.unwrap_or(config.border_radius);
+            config.opacity = o.opacity.unwrap_or(config.opacity);
+            config.mode.hide_applets(&o.hidden_applets);
         }
-        priority
+        config
     }
 
     /// get margin between the panel and the edge of the output
@@ -366,6 +707,11 @@ impl CosmicPanelConfig {
         self.background.clone()
     }
 
+    /// get the alpha of the background color, including the opacity of the panel
+    pub fn background_alpha(&self) -> f32 {
+        self.background.color().map_or(1.0, |c| c[3]) * self.opacity
+    }
+
     pub fn spacing(&self) -> u32 {
         self.spacing
     }
@@ -378,51 +724,32 @@ impl CosmicPanelConfig {
         self.autohide.clone()
     }
 
-    /// get whether the panel should expand to cover the edges of the output
-    pub fn expand_to_edges(&self) -> bool {
-        self.expand_to_edges
+    /// get whether the panel is sized to its applets, rather than expanding to cover the edges of the output
+    pub fn is_dock(&self) -> bool {
+        matches!(self.mode, PanelMode::Dock { .. })
     }
 
-    pub fn plugins_left(&self) -> Option<Vec<String>> {
-        if self.expand_to_edges {
-            self.plugins_wings.as_ref().map(|w| w.0.clone())
-        } else {
-            None
+    /// get the enabled applets on the left / top of the panel
+    pub fn plugins_left(&self) -> Option<Vec<AppletEntry>> {
+        match &self.mode {
+            PanelMode::Bar { wings, .. } => wings.as_ref().map(|w| enabled(&w.0)),
+            PanelMode::Dock { .. } => None,
         }
     }
 
-    pub fn plugins_center(&self) -> Option<Vec<String>> {
-        if self.expand_to_edges || self.plugins_wings.is_none() {
-            self.plugins_center.clone()
-        } else if let Some(plugins_center) = self.plugins_center.as_ref() {
-            let (left, right) = self.plugins_wings.as_ref().unwrap();
-            Some(
-                left.clone()
-                    .into_iter()
-                    .chain(
-                        plugins_center
-                            .clone()
-                            .into_iter()
-                            .chain(right.clone().into_iter()),
-                    )
-                    .collect(),
-            )
-        } else {
-            let (left, right) = self.plugins_wings.as_ref().unwrap();
-            Some(
-                left.clone()
-                    .into_iter()
-                    .chain(right.clone().into_iter())
-                    .collect(),
-            )
+    /// get the enabled applets in the center of the panel
+    pub fn plugins_center(&self) -> Option<Vec<AppletEntry>> {
+        match &self.mode {
+            PanelMode::Bar { center, .. } => center.as_ref().map(|c| enabled(c)),
+            PanelMode::Dock { applets, .. } => Some(enabled(applets)),
         }
     }
 
-    pub fn plugins_right(&self) -> Option<Vec<String>> {
-        if self.expand_to_edges {
-            self.plugins_wings.as_ref().map(|w| w.1.clone())
-        } else {
-            None
+    /// get the enabled applets on the right / bottom of the panel
+    pub fn plugins_right(&self) -> Option<Vec<AppletEntry>> {
+        match &self.mode {
+            PanelMode::Bar { wings, .. } => wings.as_ref().map(|w| enabled(&w.1)),
+            PanelMode::Dock { .. } => None,
         }
     }
 
@@ -455,15 +782,21 @@ impl CosmicPanelConfig {
         output_dims: Option<(u32, u32)>,
         suggested_length: Option<u32>,
     ) -> (Option<Range<u32>>, Option<Range<u32>>) {
-        let mut bar_thickness = match &self.size {
-            PanelSize::XS => 8..61,
-            PanelSize::S => 8..81,
-            PanelSize::M => 8..101,
-            PanelSize::L => 8..121,
-            PanelSize::XL => 8..141,
+        let bar_thickness = match &self.size {
+            // the configured thickness is exact, so only the anchor gap is added
+            PanelSize::Custom { thickness, .. } => {
+                let thickness = *thickness + self.get_effective_anchor_gap();
+                thickness..thickness + 1
+            }
+            // padding which leaves no space for the applets is reported by validation,
+            // so the range is only kept non-empty here
+            size => {
+                let end = (size.max_thickness() + 1)
+                    .saturating_sub(2 * self.padding)
+                    .max(MIN_THICKNESS + 1);
+                MIN_THICKNESS..end
+            }
         };
-        assert!(2 * self.padding < bar_thickness.end);
-        bar_thickness.end -= 2 * self.padding;
         let o_h = suggested_length.unwrap_or_else(|| output_dims.unwrap_or_default().1);
         let o_w = suggested_length.unwrap_or_else(|| output_dims.unwrap_or_default().0);
 
@@ -477,6 +810,15 @@ impl CosmicPanelConfig {
         let entry_name = format!("{NAME}.{}", name);
         Config::new(&entry_name, VERSION)
     }
+
+    /// write the applets of the profile, which keeps the instance names given by `name_instances`
+    pub fn write_mode(&self) -> Result<(), cosmic_config::Error> {
+        Self::cosmic_config(&self.name)?.set("mode", &self.mode)
+    }
+}
+
+fn enabled(entries: &[AppletEntry]) -> Vec<AppletEntry> {
+    entries.iter().filter(|e| e.is_enabled()).cloned().collect()
 }
 
 #[cfg(feature = "wayland-rs")]
@@ -0,0 +1,447 @@
+//! Validation of the panel config
+
+use std::{collections::HashSet, fmt::Display, path::Path};
+
+use crate::{
+    panel_config::MIN_THICKNESS, parse_color, AppletEntry, CosmicPanelBackground,
+    CosmicPanelConfig, CosmicPanelContainerConfig, PanelSize, ResourceLimits,
+};
+
+/// valid nice levels of a process
+const NICE_RANGE: std::ops::RangeInclusive<i32> = -20..=19;
+/// smallest address space in bytes which an applet can start with
+const MIN_ADDRESS_SPACE: u64 = 64 * 1024 * 1024;
+/// fewest open files which an applet can start with, its standard streams and sockets take several
+const MIN_OPEN_FILES: u64 = 16;
+
+/// A problem with a config value
+#[derive(Debug, Clone, PartialEq)]
+pub enum InvalidValue {
+    /// the profile has no name
+    EmptyName,
+    /// another profile has the same name
+    DuplicateName,
+    /// the padding leaves no space for the applets
+    PaddingTooLarge {
+        /// configured padding
+        padding: u32,
+        /// largest padding for the configured size
+        max: u32,
+    },
+    /// a custom size is zero
+    ZeroSize,
+    /// the opacity is not between 0 and 1
+    OpacityOutOfRange(f32),
+    /// autohide and the exclusive zone are both enabled
+    AutohideWithExclusiveZone,
+    /// the autohide handle size is zero
+    ZeroHandleSize,
+    /// the autohide transition time is zero
+    ZeroTransitionTime,
+    /// a color component is not between 0 and 1
+    ColorOutOfRange,
+    /// a CSS color can't be parsed
+    InvalidColor(String),
+    /// a gradient has no color stops
+    EmptyGradient,
+    /// a gradient stop is not between 0 and 1
+    GradientStopOutOfRange(f32),
+    /// the background image file doesn't exist
+    MissingImage(String),
+    /// a nice level is not between -20 and 19
+    NiceOutOfRange(i32),
+    /// the address space limit is too small for an applet to start
+    AddressSpaceTooSmall(u64),
+    /// the open files limit is too small for an applet to start
+    TooFewOpenFiles(u64),
+    /// the CPU time limit is zero, which kills an applet as soon as it starts
+    ZeroCpuTime,
+    /// entries of the same applet have the same instance id
+    DuplicateApplet(String),
+    /// the ping interval of the watchdog is zero
+    ZeroPingInterval,
+}
+
+impl Display for InvalidValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidValue::EmptyName => write!(f, "the profile name is empty"),
+            InvalidValue::DuplicateName => write!(f, "another profile has the same name"),
+            InvalidValue::PaddingTooLarge { padding, max } => write!(
+                f,
+                "padding {} leaves no space for applets, it should be at most {}",
+                padding, max
+            ),
+            InvalidValue::ZeroSize => write!(f, "the thickness and icon size should be > 0"),
+            InvalidValue::OpacityOutOfRange(o) => {
+                write!(f, "opacity {} should be between 0 and 1", o)
+            }
+            InvalidValue::AutohideWithExclusiveZone => {
+                write!(f, "autohide can't be used with an exclusive zone")
+            }
+            InvalidValue::ZeroHandleSize => write!(f, "the handle size should be > 0"),
+            InvalidValue::ZeroTransitionTime => write!(f, "the transition time should be > 0"),
+            InvalidValue::ColorOutOfRange => {
+                write!(f, "color components should be between 0 and 1")
+            }
+            InvalidValue::InvalidColor(c) => write!(f, "{} is not a valid CSS color", c),
+            InvalidValue::EmptyGradient => write!(f, "the gradient has no color stops"),
+            InvalidValue::GradientStopOutOfRange(p) => {
+                write!(f, "gradient stop {} should be between 0 and 1", p)
+            }
+            InvalidValue::MissingImage(p) => write!(f, "the image {} doesn't exist", p),
+            InvalidValue::NiceOutOfRange(n) => {
+                write!(f, "nice level {} should be between -20 and 19", n)
+            }
+            InvalidValue::AddressSpaceTooSmall(bytes) => write!(
+                f,
+                "address space limit {} bytes should be at least {} bytes, the limit is ignored",
+                bytes, MIN_ADDRESS_SPACE
+            ),
+            InvalidValue::TooFewOpenFiles(files) => write!(
+                f,
+                "open files limit {} should be at least {}, the limit is ignored",
+                files, MIN_OPEN_FILES
+            ),
+            InvalidValue::ZeroCpuTime => {
+                write!(f, "the CPU time limit should be > 0, the limit is ignored")
+            }
+            InvalidValue::DuplicateApplet(id) => write!(
+                f,
+                "{} is in the panel more than once, its entries should have different instance names",
+                id
+            ),
+            InvalidValue::ZeroPingInterval => {
+                write!(f, "the ping interval should be > 0, or None to not ping applets")
+            }
+        }
+    }
+}
+
+/// A problem with a value of a panel profile
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationIssue {
+    /// name of the profile
+    pub profile: String,
+    /// key of the value in the profile
+    pub field: &'static str,
+    /// the problem with the value
+    pub kind: InvalidValue,
+}
+
+impl Display for ValidationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}: {}", self.profile, self.field, self.kind)
+    }
+}
+
+/// Result of validating a config
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Validation {
+    /// problems which make a profile unusable
+    pub errors: Vec<ValidationIssue>,
+    /// problems which are fixed by clamping the value
+    pub warnings: Vec<ValidationIssue>,
+}
+
+impl Validation {
+    /// whether there are no errors
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    fn error(&mut self, profile: &str, field: &'static str, kind: InvalidValue) {
+        self.errors.push(ValidationIssue {
+            profile: profile.to_string(),
+            field,
+            kind,
+        });
+    }
+
+    fn warning(&mut self, profile: &str, field: &'static str, kind: InvalidValue) {
+        self.warnings.push(ValidationIssue {
+            profile: profile.to_string(),
+            field,
+            kind,
+        });
+    }
+}
+
+impl CosmicPanelConfig {
+    /// check the values of the profile
+    pub fn validate(&self) -> Validation {
+        let mut validation = Validation::default();
+        let name = self.name.as_str();
+
+        if name.is_empty() {
+            validation.error(name, "name", InvalidValue::EmptyName);
+        }
+
+        if let Some((field, kind)) = size_issue(&self.size, self.padding) {
+            validation.error(name, field, kind);
+        }
+
+        if !(0.0..=1.0).contains(&self.opacity) {
+            validation.warning(
+                name,
+                "opacity",
+                InvalidValue::OpacityOutOfRange(self.opacity),
+            );
+        }
+
+        if let Some(kind) = self.background_issue() {
+            validation.warning(name, "background", kind);
+        }
+
+        for (_, o) in &self.overrides {
+            if o.size.is_some() || o.padding.is_some() {
+                let size = o.size.as_ref().unwrap_or(&self.size);
+                if let Some((_, kind)) = size_issue(size, o.padding.unwrap_or(self.padding)) {
+                    validation.error(name, "overrides", kind);
+                }
+            }
+            if let Some(opacity) = o.opacity.filter(|o| !(0.0..=1.0).contains(o)) {
+                validation.warning(name, "overrides", InvalidValue::OpacityOutOfRange(opacity));
+            }
+        }
+
+        let mut instances = HashSet::new();
+        for entry in self.mode.applets() {
+            if !instances.insert(entry.instance_id()) {
+                validation.warning(
+                    name,
+                    "mode",
+                    InvalidValue::DuplicateApplet(entry.instance_id()),
+                );
+            }
+        }
+
+        for kind in resource_limits_issues(&self.resource_limits) {
+            validation.warning(name, "resource_limits", kind);
+        }
+        for entry in self.mode.applets() {
+            if let AppletEntry::Config(config) = entry {
+                for kind in resource_limits_issues(&config.resource_limits) {
+                    validation.warning(name, "mode", kind);
+                }
+                if config.watchdog.as_ref().and_then(|w| w.ping_interval) == Some(0) {
+                    validation.warning(name, "mode", InvalidValue::ZeroPingInterval);
+                }
+            }
+        }
+
+        if self.watchdog.ping_interval == Some(0) {
+            validation.warning(name, "watchdog", InvalidValue::ZeroPingInterval);
+        }
+
+        if let Some(autohide) = self.autohide.as_ref() {
+            if self.exclusive_zone {
+                validation.warning(
+                    name,
+                    "exclusive_zone",
+                    InvalidValue::AutohideWithExclusiveZone,
+                );
+            }
+            if autohide.handle_size == 0 {
+                validation.warning(name, "autohide", InvalidValue::ZeroHandleSize);
+            }
+            if autohide.transition_time == 0 {
+                validation.warning(name, "autohide", InvalidValue::ZeroTransitionTime);
+            }
+        }
+
+        validation
+    }
+
+    /// clamp the values reported as warnings by `validate` to usable values
+    pub fn clamp(&mut self) {
+        self.opacity = clamp_unit(self.opacity);
+        for (_, o) in &mut self.overrides {
+            o.opacity = o.opacity.map(clamp_unit);
+        }
+        self.name_instances();
+        clamp_resource_limits(&mut self.resource_limits);
+        for entry in self.mode.applets_mut() {
+            if let AppletEntry::Config(config) = entry {
+                clamp_resource_limits(&mut config.resource_limits);
+                if let Some(watchdog) = config.watchdog.as_mut() {
+                    watchdog.ping_interval = watchdog.ping_interval.filter(|i| *i > 0);
+                }
+            }
+        }
+        self.watchdog.ping_interval = self.watchdog.ping_interval.filter(|i| *i > 0);
+        if let Some(autohide) = self.autohide.as_mut() {
+            self.exclusive_zone = false;
+            autohide.handle_size = autohide.handle_size.max(1);
+            autohide.transition_time = autohide.transition_time.max(1);
+        }
+        match &mut self.background {
+            CosmicPanelBackground::Color(c) => c.iter_mut().for_each(|c| *c = clamp_unit(*c)),
+            CosmicPanelBackground::Rgba(c) => c.iter_mut().for_each(|c| *c = clamp_unit(*c)),
+            CosmicPanelBackground::Gradient(stops) => stops
+                .iter_mut()
+                .for_each(|s| s.position = clamp_unit(s.position)),
+            _ => {}
+        }
+        // the remaining problems can't be clamped, so fall back to the theme
+        if self.background_issue().is_some() {
+            self.background = CosmicPanelBackground::ThemeDefault;
+        }
+    }
+
+    /// give the later entries of an applet without a distinct instance name a numbered one
+    /// returns whether an entry was named, the names should then be written with `write_mode`
+    /// so each entry keeps its instance when the entries are reordered
+    pub fn name_instances(&mut self) -> bool {
+        let mut named = false;
+        let mut instances = HashSet::new();
+        for entry in self.mode.applets_mut() {
+            let mut n = 2;
+            while !instances.insert(entry.instance_id()) {
+                *entry = entry.clone().with_instance(n.to_string());
+                n += 1;
+                named = true;
+            }
+        }
+        named
+    }
+
+    /// first problem with the background
+    fn background_issue(&self) -> Option<InvalidValue> {
+        let out_of_range = |c: &[f32]| c.iter().any(|c| !(0.0..=1.0).contains(c));
+        match &self.background {
+            CosmicPanelBackground::Color(c) if out_of_range(c) => {
+                Some(InvalidValue::ColorOutOfRange)
+            }
+            CosmicPanelBackground::Rgba(c) if out_of_range(c) => {
+                Some(InvalidValue::ColorOutOfRange)
+            }
+            CosmicPanelBackground::Css(c) if parse_color(c).is_none() => {
+                Some(InvalidValue::InvalidColor(c.clone()))
+            }
+            CosmicPanelBackground::Gradient(stops) if stops.is_empty() => {
+                Some(InvalidValue::EmptyGradient)
+            }
+            CosmicPanelBackground::Gradient(stops) => stops.iter().find_map(|s| {
+                if parse_color(&s.color).is_none() {
+                    Some(InvalidValue::InvalidColor(s.color.clone()))
+                } else if !(0.0..=1.0).contains(&s.position) {
+                    Some(InvalidValue::GradientStopOutOfRange(s.position))
+                } else {
+                    None
+                }
+            }),
+            CosmicPanelBackground::Image { path, .. } if !Path::new(path).is_file() => {
+                Some(InvalidValue::MissingImage(path.clone()))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl CosmicPanelContainerConfig {
+    /// check the values of each profile, and that profile names are unique
+    pub fn validate(&self) -> Validation {
+        merge(self.validate_profiles())
+    }
+
+    /// remove profiles with errors and clamp values with warnings in the remaining profiles
+    /// returns the problems which were found
+    pub fn sanitize(&mut self) -> Validation {
+        let mut validations = self.validate_profiles().into_iter();
+        let mut found = Vec::with_capacity(self.config_list.len());
+        self.config_list.retain_mut(|config| {
+            let validation = validations.next().unwrap_or_default();
+            let is_valid = validation.is_valid();
+            if is_valid {
+                config.clamp();
+            }
+            found.push(validation);
+            is_valid
+        });
+        merge(found)
+    }
+
+    fn validate_profiles(&self) -> Vec<Validation> {
+        let mut names = HashSet::new();
+        self.config_list
+            .iter()
+            .map(|config| {
+                let mut validation = config.validate();
+                if !names.insert(config.name.as_str()) {
+                    validation.error(&config.name, "name", InvalidValue::DuplicateName);
+                }
+                validation
+            })
+            .collect()
+    }
+}
+
+/// problem with the size, or with the padding for the size, and the key of the value
+fn size_issue(size: &PanelSize, padding: u32) -> Option<(&'static str, InvalidValue)> {
+    match *size {
+        PanelSize::Custom {
+            thickness,
+            icon_size,
+        } if thickness == 0 || icon_size == 0 => Some(("size", InvalidValue::ZeroSize)),
+        _ => {
+            let max = max_padding(size);
+            (padding > max).then_some(("padding", InvalidValue::PaddingTooLarge { padding, max }))
+        }
+    }
+}
+
+/// largest padding which leaves space for the applets
+fn max_padding(size: &PanelSize) -> u32 {
+    match size {
+        PanelSize::Custom { thickness, .. } => thickness.saturating_sub(1) / 2,
+        size => size.max_thickness().saturating_sub(MIN_THICKNESS) / 2,
+    }
+}
+
+/// problems with resource limits, which keep applets from starting
+fn resource_limits_issues(limits: &ResourceLimits) -> Vec<InvalidValue> {
+    let mut issues = Vec::new();
+    if let Some(nice) = limits.nice.filter(|n| !NICE_RANGE.contains(n)) {
+        issues.push(InvalidValue::NiceOutOfRange(nice));
+    }
+    if let Some(bytes) = limits.address_space.filter(|b| *b < MIN_ADDRESS_SPACE) {
+        issues.push(InvalidValue::AddressSpaceTooSmall(bytes));
+    }
+    if let Some(files) = limits.open_files.filter(|f| *f < MIN_OPEN_FILES) {
+        issues.push(InvalidValue::TooFewOpenFiles(files));
+    }
+    if limits.cpu_time == Some(0) {
+        issues.push(InvalidValue::ZeroCpuTime);
+    }
+    issues
+}
+
+/// clamp the nice level, and drop the limits which keep applets from starting
+fn clamp_resource_limits(limits: &mut ResourceLimits) {
+    limits.nice = limits
+        .nice
+        .map(|n| n.clamp(*NICE_RANGE.start(), *NICE_RANGE.end()));
+    limits.address_space = limits.address_space.filter(|b| *b >= MIN_ADDRESS_SPACE);
+    limits.open_files = limits.open_files.filter(|f| *f >= MIN_OPEN_FILES);
+    limits.cpu_time = limits.cpu_time.filter(|t| *t > 0);
+}
+
+/// clamp to 0..=1, treating NaN as 1
+fn clamp_unit(v: f32) -> f32 {
+    if v.is_nan() {
+        1.0
+    } else {
+        v.clamp(0.0, 1.0)
+    }
+}
+
+fn merge(validations: Vec<Validation>) -> Validation {
+    validations
+        .into_iter()
+        .fold(Validation::default(), |mut acc, mut v| {
+            acc.errors.append(&mut v.errors);
+            acc.warnings.append(&mut v.warnings);
+            acc
+        })
+}